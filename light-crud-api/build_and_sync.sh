@@ -9,13 +9,13 @@ while [[ "$#" -gt 0 ]]; do
 done
 
 if [ "$release" = true ]; then
-    cargo build --release --target aarch64-unknown-linux-gnu                                                              
+    cargo build --release --features rpi --target aarch64-unknown-linux-gnu                                                              
     if [ $? -eq 0 ]; then                                                       
         rsync -avz --delete /home/joel/GH/Lights/light-crud-api/target/aarch64-unknown-linux-gnu/release/light-crud-api pi@192.168.2.39:/home/pi/light-crud-api                                 
     fi 
 else
     echo "Warning: This seems to not work on the raspberry pi, use --release"
-    cargo build --target aarch64-unknown-linux-gnu                                                              
+    cargo build --features rpi --target aarch64-unknown-linux-gnu                                                              
     if [ $? -eq 0 ]; then                                                       
         rsync -avz --delete /home/joel/GH/Lights/light-crud-api/target/aarch64-unknown-linux-gnu/debug/light-crud-api pi@192.168.2.39:/home/pi/light-crud-api                                 
     fi 
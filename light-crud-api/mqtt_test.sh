@@ -0,0 +1,71 @@
+#!/bin/bash
+# Checks the MQTT bridge against a local Mosquitto broker.
+#
+# Start a broker with `mosquitto -p 1883`, add
+#     [mqtt]
+#     host = "localhost"
+# to config.toml and start the server, then run this. It needs mosquitto_pub and mosquitto_sub
+# from the mosquitto-clients package. Pass --shutdown to also check `system/shutdown` shuts it down.
+
+host=localhost
+port=1883
+root=command
+while [[ "$#" -gt 0 ]]; do
+    case $1 in
+        --host) host=$2; shift ;;
+        --port) port=$2; shift ;;
+        --prefix) root="$2/command"; shift ;;
+        --shutdown) shutdown=true ;;
+        *) echo "Unknown parameter passed: $1"; exit 1 ;;
+    esac
+    shift
+done
+
+failures=0
+
+publish() {
+    mosquitto_pub -h "$host" -p "$port" -t "$root/$1" -m "$2"
+}
+
+# asks for $1 and checks the answer on the response topic matches the pattern $2
+expect_response() {
+    mosquitto_sub -h "$host" -p "$port" -t "$root/$1/response" -C 1 -W 5 > /tmp/mqtt_test_response &
+    subscriber=$!
+    sleep 0.5
+    publish "$1/request" ""
+    wait $subscriber
+    response=$(cat /tmp/mqtt_test_response)
+    if [[ "$response" =~ $2 ]]; then
+        echo "ok    $1: $response"
+    else
+        echo "FAIL  $1: expected $2, got '$response'"
+        failures=$((failures + 1))
+    fi
+}
+
+publish lights/brightness/set 0.5
+sleep 0.5
+expect_response lights/brightness '"level":128'
+
+publish lights/color/all/set '#000000'
+publish lights/color/0/set '[255, 136, 0]'
+sleep 0.5
+expect_response lights/color/0 '"color":"#ff8800"'
+expect_response lights/color/1 '"color":"#000000"'
+expect_response lights/color/nope '"error"'
+expect_response system/fps '"measured_fps"'
+expect_response lights/nothing '"error":"nothing listens on lights/nothing/request"'
+
+if [ "$shutdown" = true ]; then
+    publish system/shutdown ""
+    sleep 2
+    if pgrep -x light-crud-api > /dev/null; then
+        echo "FAIL  system/shutdown: the server is still running"
+        failures=$((failures + 1))
+    else
+        echo "ok    system/shutdown"
+    fi
+fi
+
+echo "$failures failed"
+[ $failures -eq 0 ]
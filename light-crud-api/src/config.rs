@@ -7,13 +7,33 @@ use std::path::Path;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use toml;
 
-use crate::database::animation::Animation;
+use crate::events::EventBus;
+use crate::lights::brightness::BrightnessRequest;
+use crate::lights::ddp::SharedDdpStats;
+use crate::lights::live::LiveInput;
+use crate::lights::playback::{Interpolation, PlayRequest};
+use crate::lights::player::PlayerCommand;
+use crate::lights::status::SharedStatus;
+use crate::lights::stream::FrameStream;
+use crate::lights::transition::Transition;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct TOMLConfig {
     pub database: DatabaseConfig,
     pub web: WebConfig,
     pub debug: DebugConfig,
+    #[serde(default)]
+    pub lights: LightsConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<LocationConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e131: Option<E131Config>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artnet: Option<ArtNetConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ddp: Option<DdpConfig>,
 }
 
 #[derive(Debug)]
@@ -36,8 +56,20 @@ pub struct Config {
     pub database: DatabaseConfig,
     pub web: WebConfig,
     pub debug: DebugConfig,
-    pub animation_comms: CompactSender<Animation>,
-    pub brightness_comms: CompactSender<u8>,
+    pub lights: LightsConfig,
+    pub location: Option<LocationConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub e131: Option<E131Config>,
+    pub artnet: Option<ArtNetConfig>,
+    pub ddp: Option<DdpConfig>,
+    pub animation_comms: CompactSender<PlayRequest>,
+    pub brightness_comms: CompactSender<BrightnessRequest>,
+    pub player_comms: CompactSender<PlayerCommand>,
+    pub live_comms: CompactSender<LiveInput>,
+    pub lights_status: SharedStatus,
+    pub frame_stream: FrameStream,
+    pub events: EventBus,
+    pub ddp_stats: SharedDdpStats,
     // pub sending_channel: tokio::sync::mpsc::Sender<Animation>,
     // pub receving_channel: tokio::sync::mpsc::Receiver<Animation>,
 }
@@ -51,7 +83,578 @@ pub struct DebugConfig {
     pub on_raspberry_pi: bool,
     pub enable_webserver: bool,
     pub enable_lights: bool,
-    pub enable_timed_brightness: bool,
+    /// Run the schedules stored in the database, this used to be the timed brightness task
+    #[serde(alias = "enable_timed_brightness")]
+    pub enable_scheduler: bool,
+}
+
+/// Where the lights are, so sunrise and sunset can be worked out
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LocationConfig {
+    /// Degrees, north is positive
+    pub latitude: f64,
+    /// Degrees, east is positive
+    pub longitude: f64,
+    /// IANA name such as "America/Toronto", the system timezone if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl LocationConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-90.0..=90.0).contains(&self.latitude) {
+            return Err(format!(
+                "location.latitude {} must be between -90 and 90",
+                self.latitude
+            ));
+        }
+        if !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(format!(
+                "location.longitude {} must be between -180 and 180",
+                self.longitude
+            ));
+        }
+        if let Some(timezone) = &self.timezone {
+            if timezone.parse::<chrono_tz::Tz>().is_err() {
+                return Err(format!(
+                    "location.timezone {timezone:?} is not a known timezone"
+                ));
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// The broker to take commands from, the MQTT bridge only runs when this is set
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    /// Put in front of every topic, e.g. "walkway" listens on "walkway/command/..."
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl MqttConfig {
+    /// The topic every command is under
+    pub fn topic_root(&self) -> String {
+        let prefix = self.prefix.trim_matches('/');
+        return match prefix.is_empty() {
+            true => String::from("command"),
+            false => format!("{prefix}/command"),
+        };
+    }
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    String::from("light-crud-api")
+}
+
+/// Where an output channel's LEDs are in the DMX universes, three slots of red, green and blue
+/// per LED. A channel with more LEDs than fit carries on from slot 1 of the next universe.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UniverseConfig {
+    /// Name of the channel in `lights.channels`
+    pub channel: String,
+    /// Universe the first LED is in
+    pub universe: u16,
+    /// DMX slot of the first LED's red, counting from 1
+    #[serde(default = "default_start_channel")]
+    pub start_channel: u16,
+}
+
+fn default_start_channel() -> u16 {
+    1
+}
+
+impl UniverseConfig {
+    /// Checks the channels exist and fit in `universes`. `section` names the list in errors.
+    pub fn validate_all(
+        outputs: &[UniverseConfig],
+        universes: std::ops::RangeInclusive<u16>,
+        lights: &LightsConfig,
+        section: &str,
+    ) -> Result<(), String> {
+        if outputs.is_empty() {
+            return Err(format!("{section} needs at least one output channel"));
+        }
+        for (index, output) in outputs.iter().enumerate() {
+            let channel = match lights.channel_index(&output.channel) {
+                Some(channel) => &lights.channels[channel],
+                None => {
+                    return Err(format!(
+                        "{section}[{index}] uses unknown channel {:?}",
+                        output.channel
+                    ))
+                }
+            };
+            if !(1..=510).contains(&output.start_channel) {
+                return Err(format!(
+                    "{section}[{index}] start_channel must be between 1 and 510"
+                ));
+            }
+            let first_universe = (512 - output.start_channel as usize + 1) / 3;
+            let more_universes = channel
+                .led_count
+                .saturating_sub(first_universe)
+                .div_ceil(170);
+            let last = output.universe as usize + more_universes;
+            if !universes.contains(&output.universe) || last > *universes.end() as usize {
+                return Err(format!(
+                    "{section}[{index}] ({}) needs universes {} to {last}, they must be between {} and {}",
+                    output.channel,
+                    output.universe,
+                    universes.start(),
+                    universes.end()
+                ));
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Listens for DMX data sent over E1.31 (sACN), e.g. from xLights, and shows it in place of
+/// the animation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct E131Config {
+    #[serde(default = "default_e131_port")]
+    pub port: u16,
+    /// Join the multicast group of each universe as well as taking unicast packets
+    #[serde(default = "default_multicast")]
+    pub multicast: bool,
+    /// Address of the network interface to join the multicast groups on, 0.0.0.0 lets the
+    /// system pick
+    #[serde(default = "default_multicast_interface")]
+    pub interface: String,
+    /// How long without data before going back to the animation, `lights.live_timeout_ms` if
+    /// not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    pub outputs: Vec<UniverseConfig>,
+}
+
+impl E131Config {
+    pub fn validate(&self, lights: &LightsConfig) -> Result<(), String> {
+        if self.interface.parse::<std::net::Ipv4Addr>().is_err() {
+            return Err(format!(
+                "e131.interface {:?} is not an IPv4 address",
+                self.interface
+            ));
+        }
+        return UniverseConfig::validate_all(&self.outputs, 1..=63999, lights, "e131.outputs");
+    }
+}
+
+fn default_e131_port() -> u16 {
+    5568
+}
+
+fn default_multicast() -> bool {
+    true
+}
+
+fn default_multicast_interface() -> String {
+    String::from("0.0.0.0")
+}
+
+/// Listens for Art-Net DMX and answers polls so controllers can find the lights. Universes are
+/// 15 bit port addresses, net, sub-net and universe together.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArtNetConfig {
+    #[serde(default = "default_artnet_port")]
+    pub port: u16,
+    /// What controllers list the node as
+    #[serde(default = "default_artnet_name")]
+    pub name: String,
+    /// How long without data before going back to the animation, `lights.live_timeout_ms` if
+    /// not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    pub outputs: Vec<UniverseConfig>,
+}
+
+impl ArtNetConfig {
+    pub fn validate(&self, lights: &LightsConfig) -> Result<(), String> {
+        return UniverseConfig::validate_all(&self.outputs, 0..=32767, lights, "artnet.outputs");
+    }
+}
+
+fn default_artnet_port() -> u16 {
+    6454
+}
+
+fn default_artnet_name() -> String {
+    String::from("light-crud-api")
+}
+
+/// Listens for DDP, e.g. from WLED or xLights
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DdpConfig {
+    #[serde(default = "default_ddp_port")]
+    pub port: u16,
+    /// How long without data before going back to the animation, `lights.live_timeout_ms` if
+    /// not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+fn default_ddp_port() -> u16 {
+    4048
+}
+
+/// Which backend the light loop renders frames to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputType {
+    /// The physical strips, driven through rpi_ws281x. Needs the `rpi` feature.
+    Ws281x,
+    /// An in memory strip, useful for running the light loop off the Pi.
+    Simulated,
+    /// Draws every rendered frame in the terminal as rows of coloured blocks.
+    Terminal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LightsConfig {
+    /// When left out, the output is picked from `debug.on_raspberry_pi`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputType>,
+    /// How many blocks wide the terminal preview is allowed to draw each channel.
+    #[serde(default = "default_terminal_width")]
+    pub terminal_width: usize,
+    /// Signal frequency of the strips in Hz
+    #[serde(default = "default_frequency")]
+    pub frequency: u32,
+    #[serde(default = "default_dma")]
+    pub dma: i32,
+    /// Frames per second rendered while blending, e.g. during a transition or between keyframes
+    #[serde(default = "default_refresh_rate")]
+    pub refresh_rate: f64,
+    /// Used when a play request does not ask for an interpolation mode
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// The physical channels, in rpi_ws281x channel order
+    #[serde(default = "default_channels")]
+    pub channels: Vec<ChannelConfig>,
+    /// How a frame is laid out over the channels. When empty every channel shows the start of
+    /// the frame, so the strips mirror each other.
+    #[serde(default)]
+    pub segments: Vec<SegmentConfig>,
+    #[serde(default)]
+    pub power: PowerConfig,
+    /// Used when a play request does not ask for a transition
+    #[serde(default)]
+    pub transition: Transition,
+    /// How long live frames keep showing with nothing new coming in before the animation
+    /// carries on
+    #[serde(default = "default_live_timeout_ms")]
+    pub live_timeout_ms: u64,
+}
+
+/// Estimated current draw of the strips, used to keep each supply under its budget
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PowerConfig {
+    /// Current drawn by one colour of one LED at full intensity
+    #[serde(default = "default_milliamps_per_color")]
+    pub milliamps_per_color: f64,
+    /// Current drawn by one LED when it is off
+    #[serde(default = "default_idle_milliamps_per_led")]
+    pub idle_milliamps_per_led: f64,
+    /// No supplies means no limiting
+    #[serde(default)]
+    pub supplies: Vec<SupplyConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SupplyConfig {
+    pub name: String,
+    pub max_amps: f64,
+    /// Names of the channels in `lights.channels` powered by this supply
+    pub channels: Vec<String>,
+}
+
+fn default_milliamps_per_color() -> f64 {
+    20.0
+}
+
+fn default_idle_milliamps_per_led() -> f64 {
+    1.0
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        PowerConfig {
+            milliamps_per_color: default_milliamps_per_color(),
+            idle_milliamps_per_led: default_idle_milliamps_per_led(),
+            supplies: Vec::new(),
+        }
+    }
+}
+
+fn default_live_timeout_ms() -> u64 {
+    2500
+}
+
+fn default_terminal_width() -> usize {
+    125
+}
+
+fn default_frequency() -> u32 {
+    800_000
+}
+
+fn default_dma() -> i32 {
+    10
+}
+
+fn default_refresh_rate() -> f64 {
+    30.0
+}
+
+fn default_channels() -> Vec<ChannelConfig> {
+    vec![
+        ChannelConfig {
+            name: "entryway".to_string(),
+            pin: 12,
+            led_count: 250,
+            strip_type: StripType::Ws2811Bgr,
+            brightness: 100,
+            gamma: default_gamma(),
+            white_balance: default_white_balance(),
+            color_temperature: None,
+        },
+        ChannelConfig {
+            name: "front_of_house".to_string(),
+            pin: 19,
+            led_count: 250,
+            strip_type: StripType::Ws2811Bgr,
+            brightness: 100,
+            gamma: default_gamma(),
+            white_balance: default_white_balance(),
+            color_temperature: None,
+        },
+    ]
+}
+
+/// The colour order and chipset of a strip, mirrors `rs_ws281x::StripType`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StripType {
+    Sk6812Rgbw,
+    Sk6812Rbgw,
+    Sk6812Gbrw,
+    Sk6812Grbw,
+    Sk6812Brgw,
+    Sk6812Bgrw,
+    Ws2811Rgb,
+    Ws2811Rbg,
+    Ws2811Grb,
+    Ws2811Gbr,
+    Ws2811Brg,
+    Ws2811Bgr,
+    Ws2812,
+    Sk6812,
+    Sk6812W,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelConfig {
+    pub name: String,
+    /// GPIO pin the data line is on, rpi_ws281x only supports the PWM, PCM and SPI pins
+    pub pin: i32,
+    pub led_count: usize,
+    pub strip_type: StripType,
+    /// Brightness the channel starts up with
+    pub brightness: u8,
+    /// Exponent of the gamma curve applied on the way out, 1.0 leaves the colours linear
+    #[serde(default = "default_gamma")]
+    pub gamma: f64,
+    /// Red, green and blue scale factors to line up the white point between strip batches
+    #[serde(default = "default_white_balance")]
+    pub white_balance: [f64; 3],
+    /// Colour temperature in Kelvin to tint the strip towards, left out for no tint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_temperature: Option<u32>,
+}
+
+fn default_gamma() -> f64 {
+    1.0
+}
+
+fn default_white_balance() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+/// A run of pixels from a frame placed onto part of a channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SegmentConfig {
+    /// Name of the channel in `lights.channels`
+    pub channel: String,
+    /// First pixel of the frame in this segment
+    pub start: usize,
+    pub length: usize,
+    /// First LED on the channel the segment is drawn to
+    #[serde(default)]
+    pub offset: usize,
+    /// Draw the segment from the far end of the channel back towards the offset
+    #[serde(default)]
+    pub reversed: bool,
+}
+
+impl LightsConfig {
+    pub fn output_type(&self, debug: &DebugConfig) -> OutputType {
+        match self.output {
+            Some(output) => output,
+            None if debug.on_raspberry_pi => OutputType::Ws281x,
+            None => OutputType::Simulated,
+        }
+    }
+
+    /// Number of pixels in a frame that covers the whole installation
+    pub fn frame_size(&self) -> usize {
+        if self.segments.is_empty() {
+            return self
+                .channels
+                .iter()
+                .map(|channel| channel.led_count)
+                .max()
+                .unwrap_or(0);
+        }
+        return self
+            .segments
+            .iter()
+            .map(|segment| segment.start + segment.length)
+            .max()
+            .unwrap_or(0);
+    }
+
+    pub fn channel_index(&self, name: &str) -> Option<usize> {
+        return self
+            .channels
+            .iter()
+            .position(|channel| channel.name == name);
+    }
+
+    pub fn channel_lengths(&self) -> Vec<usize> {
+        return self
+            .channels
+            .iter()
+            .map(|channel| channel.led_count)
+            .collect();
+    }
+
+    /// Checks the channel layout makes sense before anything tries to drive it
+    pub fn validate(&self) -> Result<(), String> {
+        if self.channels.is_empty() {
+            return Err("lights.channels needs at least one channel".to_string());
+        }
+        if self.channels.len() > 2 {
+            return Err(format!(
+                "lights.channels has {} channels, rpi_ws281x only supports 2",
+                self.channels.len()
+            ));
+        }
+        if self.frequency == 0 {
+            return Err("lights.frequency must be greater than 0".to_string());
+        }
+        if self.refresh_rate.is_nan() || self.refresh_rate <= 0.0 {
+            return Err("lights.refresh_rate must be greater than 0".to_string());
+        }
+        for (index, channel) in self.channels.iter().enumerate() {
+            if channel.name.is_empty() {
+                return Err(format!("lights.channels[{index}] needs a name"));
+            }
+            if channel.led_count == 0 {
+                return Err(format!(
+                    "lights.channels[{index}] ({}) needs at least one LED",
+                    channel.name
+                ));
+            }
+            if channel.gamma.is_nan() || channel.gamma <= 0.0 {
+                return Err(format!(
+                    "lights.channels[{index}] ({}) gamma must be greater than 0",
+                    channel.name
+                ));
+            }
+            if channel
+                .white_balance
+                .iter()
+                .any(|scale| scale.is_nan() || *scale < 0.0)
+            {
+                return Err(format!(
+                    "lights.channels[{index}] ({}) white_balance can not be negative",
+                    channel.name
+                ));
+            }
+            if let Some(kelvin) = channel.color_temperature {
+                if !(1000..=40000).contains(&kelvin) {
+                    return Err(format!(
+                        "lights.channels[{index}] ({}) color_temperature must be between 1000K and 40000K",
+                        channel.name
+                    ));
+                }
+            }
+            for other in &self.channels[..index] {
+                if other.name == channel.name {
+                    return Err(format!(
+                        "lights.channels name {:?} is used twice",
+                        channel.name
+                    ));
+                }
+                if other.pin == channel.pin {
+                    return Err(format!(
+                        "lights.channels {:?} and {:?} are both on pin {}",
+                        other.name, channel.name, channel.pin
+                    ));
+                }
+            }
+        }
+        for (index, segment) in self.segments.iter().enumerate() {
+            let channel = match self.channel_index(&segment.channel) {
+                Some(channel) => &self.channels[channel],
+                None => {
+                    return Err(format!(
+                        "lights.segments[{index}] uses unknown channel {:?}",
+                        segment.channel
+                    ))
+                }
+            };
+            if segment.offset + segment.length > channel.led_count {
+                return Err(format!(
+                    "lights.segments[{index}] runs past the end of {:?}, {} + {} > {}",
+                    channel.name, segment.offset, segment.length, channel.led_count
+                ));
+            }
+        }
+        for (index, supply) in self.power.supplies.iter().enumerate() {
+            if supply.max_amps.is_nan() || supply.max_amps <= 0.0 {
+                return Err(format!(
+                    "lights.power.supplies[{index}] ({}) max_amps must be greater than 0",
+                    supply.name
+                ));
+            }
+            for channel in supply.channels.iter() {
+                if self.channel_index(channel).is_none() {
+                    return Err(format!(
+                        "lights.power.supplies[{index}] ({}) uses unknown channel {channel:?}",
+                        supply.name
+                    ));
+                }
+            }
+        }
+        return Ok(());
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -60,6 +663,24 @@ pub struct WebConfig {
     pub interface: String,
 }
 
+impl Default for LightsConfig {
+    fn default() -> Self {
+        LightsConfig {
+            output: None,
+            terminal_width: default_terminal_width(),
+            frequency: default_frequency(),
+            dma: default_dma(),
+            refresh_rate: default_refresh_rate(),
+            interpolation: Interpolation::default(),
+            channels: default_channels(),
+            segments: Vec::new(),
+            power: PowerConfig::default(),
+            transition: Transition::default(),
+            live_timeout_ms: default_live_timeout_ms(),
+        }
+    }
+}
+
 impl Default for WebConfig {
     fn default() -> Self {
         WebConfig {
@@ -74,7 +695,7 @@ impl Default for DebugConfig {
             on_raspberry_pi: false,
             enable_webserver: false,
             enable_lights: false,
-            enable_timed_brightness: false,
+            enable_scheduler: false,
         }
     }
 }
@@ -87,8 +708,20 @@ impl Default for Config {
             database: DatabaseConfig::default(),
             web: WebConfig::default(),
             debug: DebugConfig::default(),
+            lights: LightsConfig::default(),
+            location: None,
+            mqtt: None,
+            e131: None,
+            artnet: None,
+            ddp: None,
             animation_comms: CompactSender::new(),
             brightness_comms: CompactSender::new(),
+            player_comms: CompactSender::new(),
+            live_comms: CompactSender::new(),
+            lights_status: SharedStatus::default(),
+            frame_stream: FrameStream::new(),
+            events: EventBus::new(),
+            ddp_stats: SharedDdpStats::default(),
             // sending_channel: tx,
             // receving_channel: rx,
         }
@@ -102,8 +735,20 @@ impl From<TOMLConfig> for Config {
             database: a.database,
             web: a.web,
             debug: a.debug,
+            lights: a.lights,
+            location: a.location,
+            mqtt: a.mqtt,
+            e131: a.e131,
+            artnet: a.artnet,
+            ddp: a.ddp,
             animation_comms: CompactSender::new(),
             brightness_comms: CompactSender::new(),
+            player_comms: CompactSender::new(),
+            live_comms: CompactSender::new(),
+            lights_status: SharedStatus::default(),
+            frame_stream: FrameStream::new(),
+            events: EventBus::new(),
+            ddp_stats: SharedDdpStats::default(),
             // sending_channel: tx,
             // receving_channel: rx,
         }
@@ -123,7 +768,9 @@ pub fn read_or_create_config<P: AsRef<Path>>(path: P) -> io::Result<Config> {
     let mut toml_config = TOMLConfig::default();
     if path.as_ref().exists() {
         let content = fs::read_to_string(&path)?;
-        toml_config = toml::from_str(&content).unwrap_or_default();
+        // a table that does not parse would otherwise fall back to the defaults without a word
+        toml_config = toml::from_str(&content)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
     } else {
         let toml_string = toml::to_string(&toml_config).unwrap();
         let mut file = fs::File::create(&path)?;
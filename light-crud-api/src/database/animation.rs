@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
     routing::{delete, get, post},
@@ -8,7 +8,7 @@ use axum::{
 use std::{collections::HashMap, sync::Arc};
 
 use futures::executor::block_on;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use sqlx::{Pool, Sqlite};
@@ -18,11 +18,16 @@ use super::{
     frame_data::FrameMetadata,
     initialize::AppState,
 };
+use crate::config::LightsConfig;
+use crate::events::Event;
+use crate::lights::brightness::{send_brightness, BrightnessOptions, BrightnessTarget};
+use crate::lights::playback::{Interpolation, PlayRequest, PlaybackMode};
+use crate::lights::transition::{Transition, TransitionKind};
 
 const _EXAMPLE_DATA: &str = r#"
 {
     "animation":{
-        "frame_data":{"name":"Some String Name","speed":24.0},
+        "frame_data":{"name":"Some String Name","speed":24.0,"mode":"loop"},
         "frames:[
             {"frame":{"frame_id":1, "data":"[1,2,3]"}},
             {"frame":{"frame_id":2, "data":"[1,2,3]"}},
@@ -43,6 +48,8 @@ pub struct Animation {
     pub id: i32,
     pub name: String,
     pub speed: f64,
+    pub mode: Option<PlaybackMode>,
+    pub repeat_count: Option<u32>,
     pub frames: Vec<DataFrame>,
 }
 #[allow(dead_code, unused_variables)]
@@ -52,15 +59,19 @@ impl Animation {
             id: -1,
             name: String::from(""),
             speed: 24.0,
+            mode: None,
+            repeat_count: None,
             frames: Vec::new(),
         }
     }
-    pub fn new_with_single_frame(color: u32) -> Self {
-        let single_frame = Frame::new_with_color(color, 250);
+    pub fn new_with_single_frame(color: u32, size: usize) -> Self {
+        let single_frame = Frame::new_with_color(color, size);
         Animation {
             id: -1,
             name: String::from(""),
             speed: 24.0,
+            mode: None,
+            repeat_count: None,
             frames: vec![DataFrame::from(&single_frame)],
         }
     }
@@ -68,7 +79,7 @@ impl Animation {
     pub fn get_from_db(id: i32, db: &Pool<Sqlite>) -> Result<Self, sqlx::Error> {
         let frame_meta = match block_on(
             sqlx::query_as::<_, FrameMetadata>(
-                "SELECT id, name, speed FROM Frame_Metadata WHERE id = ? ",
+                "SELECT id, name, speed, mode, repeat_count FROM Frame_Metadata WHERE id = ? ",
             )
             .bind(id)
             .fetch_one(db),
@@ -161,12 +172,44 @@ impl Animation {
     }
 }
 
+/// Optional query parameters when playing an animation, e.g. `?transition=wipe&duration_ms=2000`
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PlayOptions {
+    pub transition: Option<TransitionKind>,
+    pub duration_ms: Option<u64>,
+    pub interpolation: Option<Interpolation>,
+    pub mode: Option<PlaybackMode>,
+    /// Number of times to play through when `mode=repeat`
+    pub repeat: Option<u32>,
+}
+
+impl PlayOptions {
+    /// Builds the request for the controller, filling in anything not given from the config
+    pub fn into_request(self, animation: Animation, lights: &LightsConfig) -> PlayRequest {
+        let mut request = PlayRequest::new(animation, lights);
+        request.transition = Transition {
+            kind: self.transition.unwrap_or(lights.transition.kind),
+            duration_ms: self.duration_ms.unwrap_or(lights.transition.duration_ms),
+        };
+        request.interpolation = self.interpolation.unwrap_or(lights.interpolation);
+        if let Some(mode) = self.mode {
+            request.mode = mode;
+        }
+        if let Some(repeat) = self.repeat {
+            request.repeat = repeat;
+        }
+        return request;
+    }
+}
+
 impl From<FrameMetadata> for Animation {
     fn from(a: FrameMetadata) -> Self {
         Animation {
             id: a.id,
             name: a.name,
             speed: a.speed,
+            mode: a.mode,
+            repeat_count: a.repeat_count.map(|count| count.max(0) as u32),
             frames: Vec::new(),
         }
     }
@@ -191,20 +234,15 @@ async fn post_animations(State(state): State<Arc<AppState>>, payload: String) ->
     todo!()
 }
 
+/// Sets every channel's level to the brightness in the path, fading to it when a duration is
+/// given. Replaces any fade that is still running.
 async fn set_brightness(
     Path(brightness_value): Path<u8>,
+    Query(options): Query<BrightnessOptions>,
     State(state): State<Arc<AppState>>,
 ) -> Response {
-    state
-        .send_to_brightness
-        .send(brightness_value)
-        .await
-        .unwrap();
-    return (
-        StatusCode::OK,
-        json!({"brightness":brightness_value}).to_string(),
-    )
-        .into_response();
+    let request = options.into_request(BrightnessTarget::AllChannels, brightness_value);
+    return send_brightness(&state, request).await;
 }
 
 // pub fn get_frame_data(id: i32, db: &Pool<Sqlite>) -> Option<FrameMetadata> {
@@ -240,6 +278,7 @@ async fn get_animations(State(state): State<Arc<AppState>>) -> Response {
 ///
 /// # Arguments
 /// * `frame_id` - Extracted from the path from /:id
+/// * `options` - How to transition to and play the animation, from the query string
 /// * `state` - Shared with the function through the router
 ///
 /// # Returns
@@ -248,6 +287,7 @@ async fn get_animations(State(state): State<Arc<AppState>>) -> Response {
 ///
 pub async fn get_animation_id(
     Path(frame_id): Path<i32>,
+    Query(options): Query<PlayOptions>,
     State(state): State<Arc<AppState>>,
 ) -> Response {
     let ani = match Animation::get_from_db(frame_id, &state.db) {
@@ -261,9 +301,10 @@ pub async fn get_animation_id(
         }
     };
 
+    let request = options.into_request(ani.clone(), &state.lights);
     state
         .send_to_controller
-        .send(ani.clone())
+        .send(request)
         .await
         .expect("Could not send data");
 
@@ -284,6 +325,10 @@ pub async fn delete_animation_id(
                 .into_response()
         }
     };
+    state.events.publish(Event::EntityDeleted {
+        entity: "animation",
+        id: frame_id as i64,
+    });
 
     return serde_json::to_string(&delete_results)
         .unwrap()
@@ -14,6 +14,8 @@ use serde_json::{json, Value};
 use sqlx::{FromRow, Pool, Sqlite};
 
 use crate::database::initialize::AppState;
+use crate::events::Event;
+use crate::lights::playback::PlayRequest;
 
 use super::{animation, frame_data::FrameMetadata};
 
@@ -288,9 +290,10 @@ pub async fn show_frame_id(
     let mut ani = animation::Animation::from(meta_frame);
     ani.frames.push(DataFrame::from(&data));
 
+    let request = PlayRequest::new(ani, &state.lights);
     state
         .send_to_controller
-        .send(ani)
+        .send(request)
         .await
         .expect("Could not send data");
 
@@ -326,7 +329,13 @@ pub async fn post_frame(
     let insert_results = frame.insert_in_db(&state.db);
 
     match insert_results {
-        Ok(stats) => return json!({"id": stats.id}).to_string().into_response(),
+        Ok(stats) => {
+            state.events.publish(Event::EntityCreated {
+                entity: "frame",
+                id: stats.id as i64,
+            });
+            return json!({"id": stats.id}).to_string().into_response();
+        }
         Err(stats) => {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -355,7 +364,13 @@ pub async fn put_frame_id(
     let update_results = frame.update_in_db(&state.db);
 
     match update_results {
-        Ok(_) => return serde_json::to_string(&frame).unwrap().into_response(),
+        Ok(_) => {
+            state.events.publish(Event::EntityUpdated {
+                entity: "frame",
+                id: database_id as i64,
+            });
+            return serde_json::to_string(&frame).unwrap().into_response();
+        }
         Err(value) => {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -374,9 +389,13 @@ pub async fn delete_frame_id(
 
     match delete_results {
         Ok(_) => {
+            state.events.publish(Event::EntityDeleted {
+                entity: "frame",
+                id: database_id as i64,
+            });
             return json!({"id": format!("row {} deleted", database_id)})
                 .to_string()
-                .into_response()
+                .into_response();
         }
         Err(error) => {
             return (
@@ -13,10 +13,13 @@ use serde_json::{json, Value};
 use sqlx::{FromRow, Pool, Sqlite};
 
 use crate::database::initialize::AppState;
+use crate::events::Event;
+use crate::lights::playback::{PlaybackMode, MAX_SPEED, MIN_SPEED};
 
 // use crate::frame::Frame;
 
-const EXAMPLE_DATA: &str = r#"{"frame_data":{"name":"Some String Name","speed":24.0}}"#;
+const EXAMPLE_DATA: &str =
+    r#"{"frame_data":{"name":"Some String Name","speed":24.0,"mode":"repeat","repeat_count":3}}"#;
 // const GET_SQL_STATEMENT: &str = "SELECT id, name, speed FROM Frame_Metadata WHERE id = ? LIMIT 1";
 // const DELETE_SQL_STATEMENT: &str = "DELETE FROM Frame_Metadata WHERE id = ? LIMIT 1";
 // const UPDATE_SQL_STATEMENT: &str = "UPDATE Frame_Metadata SET name = ?, speed= ? WHERE id = ?";
@@ -27,6 +30,10 @@ pub struct FrameMetadata {
     pub id: i32,
     pub name: String,
     pub speed: f64,
+    /// How the animation plays when it is not overridden by the play request
+    pub mode: Option<PlaybackMode>,
+    /// Number of times to play through when the mode is `repeat`
+    pub repeat_count: Option<i64>,
 }
 
 impl FrameMetadata {
@@ -40,31 +47,68 @@ impl FrameMetadata {
         };
 
         let speed = match speed_result {
-            Ok(value) => value,
+            Ok(value) if (MIN_SPEED..=MAX_SPEED).contains(&value) => value,
+            Ok(_) => {
+                return Err(json!({"error":format!(
+                    "\"speed\" must be from {MIN_SPEED} to {MAX_SPEED} frames per second"
+                )}))
+            }
             Err(value) => return Err(value),
         };
+
+        // the playback mode is optional, leaving it out plays the animation on a loop
+        let mode = match dict.get("mode") {
+            None | Some(Value::Null) => None,
+            Some(value) => match serde_json::from_value::<PlaybackMode>(value.clone()) {
+                Ok(value) => Some(value),
+                Err(error) => {
+                    return Err(
+                        json!({"error":format!("could not convert \"mode\" entry: {error}")}),
+                    )
+                }
+            },
+        };
+        let repeat_count = match dict.get("repeat_count") {
+            None | Some(Value::Null) => None,
+            Some(value) => match value.as_i64() {
+                Some(value) => Some(value),
+                None => {
+                    return Err(
+                        json!({"error":format!("could not convert \"repeat_count\" entry to a i64")}),
+                    )
+                }
+            },
+        };
         return Ok(FrameMetadata {
             id: -1,
             name: name,
             speed: speed,
+            mode: mode,
+            repeat_count: repeat_count,
         });
     }
 
     pub fn get_from_db(id: i32, db: &Pool<Sqlite>) -> Result<Self, sqlx::Error> {
         let result = block_on(
-            sqlx::query_as::<_, Self>("SELECT id, name, speed FROM Frame_Metadata WHERE id = ?")
-                .bind(id)
-                .fetch_one(db),
+            sqlx::query_as::<_, Self>(
+                "SELECT id, name, speed, mode, repeat_count FROM Frame_Metadata WHERE id = ?",
+            )
+            .bind(id)
+            .fetch_one(db),
         );
         return result;
     }
 
     pub fn update_in_db(self: &Self, db: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
         let result = block_on(
-            sqlx::query("UPDATE Frame_Metadata SET name = ?, speed= ? WHERE id = ?")
-                .bind(self.name.clone())
-                .bind(self.speed)
-                .bind(self.id)
+            sqlx::query(
+                "UPDATE Frame_Metadata SET name = ?, speed= ?, mode = ?, repeat_count = ? WHERE id = ?",
+            )
+            .bind(self.name.clone())
+            .bind(self.speed)
+            .bind(self.mode)
+            .bind(self.repeat_count)
+            .bind(self.id)
                 .execute(db),
         );
         return match result {
@@ -75,10 +119,14 @@ impl FrameMetadata {
 
     pub fn insert_in_db(self: &Self, db: &Pool<Sqlite>) -> Result<Self, sqlx::Error> {
         let result = block_on(
-            sqlx::query("INSERT INTO Frame_Metadata (name, speed) Values(?, ?)")
-                .bind(self.name.clone())
-                .bind(self.speed)
-                .execute(db),
+            sqlx::query(
+                "INSERT INTO Frame_Metadata (name, speed, mode, repeat_count) Values(?, ?, ?, ?)",
+            )
+            .bind(self.name.clone())
+            .bind(self.speed)
+            .bind(self.mode)
+            .bind(self.repeat_count)
+            .execute(db),
         );
 
         return match result {
@@ -107,8 +155,10 @@ impl FrameMetadata {
     #[allow(dead_code)]
     pub fn get_all_from_db(db: &Pool<Sqlite>) -> Vec<Self> {
         let frame_meta_results = block_on(
-            sqlx::query_as::<_, FrameMetadata>("SELECT id, name, speed FROM Frame_Metadata")
-                .fetch_all(db),
+            sqlx::query_as::<_, FrameMetadata>(
+                "SELECT id, name, speed, mode, repeat_count FROM Frame_Metadata",
+            )
+            .fetch_all(db),
         );
 
         match frame_meta_results {
@@ -157,10 +207,11 @@ fn extract_json_frame(payload: String) -> Result<FrameMetadata, Response> {
 
 pub async fn get_all_frame_data(extract::State(state): extract::State<Arc<AppState>>) -> Response {
     // TODO: add this to the frame data impl
-    let frame_results =
-        sqlx::query_as::<_, FrameMetadata>("SELECT id, name, speed FROM Frame_Metadata")
-            .fetch_all(&state.db)
-            .await;
+    let frame_results = sqlx::query_as::<_, FrameMetadata>(
+        "SELECT id, name, speed, mode, repeat_count FROM Frame_Metadata",
+    )
+    .fetch_all(&state.db)
+    .await;
 
     match frame_results {
         Ok(value) => return serde_json::to_string(&value).unwrap().into_response(),
@@ -200,9 +251,13 @@ pub async fn delete_frame_data_id(
 
     match delete_results {
         Ok(_) => {
+            state.events.publish(Event::EntityDeleted {
+                entity: "frame_data",
+                id: database_id as i64,
+            });
             return json!({"id": format!("{} deleted", database_id)})
                 .to_string()
-                .into_response()
+                .into_response();
         }
         Err(error) => {
             return (
@@ -231,9 +286,13 @@ pub async fn put_frame_data_id(
 
     match frame_results {
         Ok(_) => {
+            state.events.publish(Event::EntityUpdated {
+                entity: "frame_data",
+                id: database_id as i64,
+            });
             return serde_json::to_string(&extracted_frame_data)
                 .unwrap()
-                .into_response()
+                .into_response();
         }
         Err(value) => {
             return (
@@ -286,7 +345,13 @@ pub async fn post_frame_data(
     };
     let frame_results = extracted_frame_data.insert_in_db(&state.db);
     match frame_results {
-        Ok(stats) => return json!({"id": stats.id}).to_string().into_response(),
+        Ok(stats) => {
+            state.events.publish(Event::EntityCreated {
+                entity: "frame_data",
+                id: stats.id as i64,
+            });
+            return json!({"id": stats.id}).to_string().into_response();
+        }
         Err(stats) => {
             return (
                 StatusCode::BAD_REQUEST,
@@ -296,3 +361,27 @@ pub async fn post_frame_data(
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_speed(speed: &str) -> Result<FrameMetadata, Value> {
+        let dict: Value =
+            serde_json::from_str(&format!(r#"{{"name":"test","speed":{speed}}}"#)).unwrap();
+        return FrameMetadata::extract_from_dict(&dict);
+    }
+
+    #[test]
+    fn takes_a_speed_in_range() {
+        assert_eq!(with_speed("24.0").unwrap().speed, 24.0);
+        assert_eq!(with_speed("1000").unwrap().speed, MAX_SPEED);
+    }
+
+    #[test]
+    fn rejects_a_speed_out_of_range() {
+        assert!(with_speed("1e300").is_err());
+        assert!(with_speed("0").is_err());
+        assert!(with_speed("-24").is_err());
+    }
+}
@@ -4,33 +4,91 @@ use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
 use sqlx::{query, Error};
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
 
-use crate::config::Config;
+use crate::config::{Config, LightsConfig};
+use crate::events::{self, EventBus};
+use crate::lights;
+use crate::lights::brightness::BrightnessRequest;
+use crate::lights::ddp::SharedDdpStats;
+use crate::lights::live::LiveInput;
+use crate::lights::playback::PlayRequest;
+use crate::lights::player::PlayerCommand;
+use crate::lights::status::SharedStatus;
+use crate::lights::stream::FrameStream;
+use crate::solar::{self, SolarCalculator};
+use crate::wled::{self, SharedColor};
 
-use super::animation::Animation;
-use super::{animation, frame, frame_data, location};
+use super::playlist::SharedPlaylistTask;
+use super::{animation, frame, frame_data, location, playlist, schedule};
 
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub db: SqlitePool,
-    pub send_to_controller: tokio::sync::mpsc::Sender<Animation>,
-    pub send_to_brightness: tokio::sync::mpsc::Sender<u8>,
+    pub send_to_controller: tokio::sync::mpsc::Sender<PlayRequest>,
+    pub send_to_brightness: tokio::sync::mpsc::Sender<BrightnessRequest>,
+    pub send_to_player: tokio::sync::mpsc::Sender<PlayerCommand>,
+    pub send_to_live: tokio::sync::mpsc::Sender<LiveInput>,
+    pub lights_status: SharedStatus,
+    /// Every frame the light loop renders, for the WebSocket stream
+    pub frame_stream: FrameStream,
+    /// What has been happening, for the `/events` stream
+    pub events: EventBus,
+    pub lights: LightsConfig,
+    /// The playlist that is currently feeding the controller, by id
+    pub playlist_task: SharedPlaylistTask,
+    /// Woken whenever a schedule is created, changed or deleted so the scheduler reloads them
+    pub schedules_changed: Arc<Notify>,
+    /// None when there is no location in the config
+    pub solar: Option<SolarCalculator>,
+    /// None when the DDP receiver is not configured
+    pub ddp: Option<SharedDdpStats>,
+    /// The colour WLED clients last filled the lights with
+    pub wled_color: SharedColor,
 }
 
-pub async fn setup(config: &Config) -> Router {
-    let mut index: HashMap<&'static str, &str> = HashMap::new();
-
+/// Opens the database and collects everything the web server and the scheduler share
+pub async fn create_state(config: &Config) -> Arc<AppState> {
     let filepath = Path::new(config.database.file_path.as_str());
     let pool = get_or_create_sqlite_database(filepath).await.unwrap();
-    let state: std::sync::Arc<AppState> = std::sync::Arc::new(AppState {
+    return Arc::new(AppState {
         db: pool,
         send_to_controller: config.animation_comms.sending_channel.clone(),
         send_to_brightness: config.brightness_comms.sending_channel.clone(),
+        send_to_player: config.player_comms.sending_channel.clone(),
+        send_to_live: config.live_comms.sending_channel.clone(),
+        lights_status: config.lights_status.clone(),
+        frame_stream: config.frame_stream.clone(),
+        events: config.events.clone(),
+        lights: config.lights.clone(),
+        playlist_task: Arc::default(),
+        schedules_changed: Arc::default(),
+        solar: config.location.as_ref().map(SolarCalculator::from_config),
+        ddp: config.ddp.as_ref().map(|_| config.ddp_stats.clone()),
+        wled_color: Arc::new(Mutex::new(wled::DEFAULT_COLOR)),
     });
+}
+
+pub fn setup(state: Arc<AppState>) -> Router {
+    let mut index: HashMap<&'static str, &str> = HashMap::new();
+
     let frame_routes = frame::router(&mut index, state.clone());
     let frame_data_routes = frame_data::router(&mut index, state.clone());
     let location_routes = location::router(&mut index, state.clone());
     let animation_routes = animation::router(&mut index, state.clone());
+    let playlist_routes = playlist::router(&mut index, state.clone());
+    let schedule_routes = schedule::router(&mut index, state.clone());
+    let solar_routes = solar::router(&mut index, state.clone());
+    let lights_routes = lights::status::router(&mut index, state.clone());
+    let player_routes = lights::player::router(&mut index, state.clone());
+    let brightness_routes = lights::brightness::router(&mut index, state.clone());
+    let stream_routes = lights::stream::router(&mut index, state.clone());
+    let event_routes = events::router(&mut index, state.clone());
+    let live_routes = lights::live::router(&mut index, state.clone());
+    let ddp_routes = lights::ddp::router(&mut index, state.clone());
+    // WLED clients expect these at fixed paths, some of them outside `/json`
+    let wled_routes = wled::router(&mut index, state.clone());
 
     let app: Router = Router::new()
         .route(
@@ -40,7 +98,18 @@ pub async fn setup(config: &Config) -> Router {
         .nest("/frame", frame_routes)
         .nest("/frame_data", frame_data_routes)
         .nest("/location", location_routes)
-        .nest("/animation", animation_routes);
+        .nest("/animation", animation_routes)
+        .nest("/playlist", playlist_routes)
+        .nest("/schedule", schedule_routes)
+        .nest("/solar", solar_routes)
+        .nest("/lights", lights_routes)
+        .nest("/player", player_routes)
+        .nest("/brightness", brightness_routes)
+        .nest("/stream", stream_routes)
+        .nest("/events", event_routes)
+        .nest("/live", live_routes)
+        .nest("/ddp", ddp_routes)
+        .merge(wled_routes);
 
     return app;
 }
@@ -76,6 +145,8 @@ pub async fn create_table_structure(pool: &SqlitePool) -> Result<(), Error> {
         id INTEGER PRIMARY KEY,
         name TEXT,
         speed REAL,
+        mode TEXT,
+        repeat_count INTEGER,
         UNIQUE(name)
     )";
 
@@ -89,8 +160,102 @@ pub async fn create_table_structure(pool: &SqlitePool) -> Result<(), Error> {
         UNIQUE(parent_id, frame_id)
     )";
 
+    let playlist_sqlite = "
+    CREATE TABLE IF NOT EXISTS Playlists(
+        id INTEGER PRIMARY KEY,
+        name TEXT,
+        shuffle INTEGER,
+        repeat INTEGER,
+        UNIQUE(name)
+    )";
+
+    let playlist_entry_sqlite = "
+    CREATE TABLE IF NOT EXISTS Playlist_Entries(
+        id INTEGER PRIMARY KEY,
+        playlist_id INTEGER,
+        position INTEGER,
+        animation_id INTEGER,
+        duration_ms INTEGER,
+        loop_count INTEGER,
+        transition TEXT,
+        transition_ms INTEGER,
+        FOREIGN KEY (playlist_id) REFERENCES Playlists(id),
+        FOREIGN KEY (animation_id) REFERENCES Frame_Metadata(id) ON DELETE CASCADE,
+        UNIQUE(playlist_id, position)
+    )";
+
+    let schedule_sqlite = "
+    CREATE TABLE IF NOT EXISTS Schedules(
+        id INTEGER PRIMARY KEY,
+        name TEXT,
+        cron TEXT,
+        time_of_day TEXT,
+        solar_event TEXT,
+        offset_minutes INTEGER,
+        weekdays INTEGER,
+        action TEXT,
+        value INTEGER,
+        channel TEXT,
+        ramp_ms INTEGER,
+        curve TEXT,
+        enabled INTEGER,
+        last_fired TEXT,
+        UNIQUE(name)
+    )";
+
     query(frame_metadata_sqlite).execute(pool).await?;
     query(frame_sqlite).execute(pool).await?;
     query(location_sqlite).execute(pool).await?;
+    query(playlist_sqlite).execute(pool).await?;
+    query(playlist_entry_sqlite).execute(pool).await?;
+
+    let (schedules_existed,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind("Schedules")
+            .fetch_one(pool)
+            .await?;
+    query(schedule_sqlite).execute(pool).await?;
+    if schedules_existed == 0 {
+        // start off doing what the old hard coded timed brightness task did
+        query(
+            "INSERT INTO Schedules (name, time_of_day, weekdays, action, value, enabled) Values
+            ('Evening brightness', '16:00', 127, 'set_brightness', 100, 1),
+            ('Daytime brightness', '07:00', 127, 'set_brightness', 1, 1)",
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    // columns added after the tables were first created
+    add_column_if_missing(pool, "Frame_Metadata", "mode", "TEXT").await?;
+    add_column_if_missing(pool, "Frame_Metadata", "repeat_count", "INTEGER").await?;
+    add_column_if_missing(pool, "Schedules", "solar_event", "TEXT").await?;
+    add_column_if_missing(pool, "Schedules", "offset_minutes", "INTEGER").await?;
+    add_column_if_missing(pool, "Schedules", "channel", "TEXT").await?;
+    add_column_if_missing(pool, "Schedules", "ramp_ms", "INTEGER").await?;
+    add_column_if_missing(pool, "Schedules", "curve", "TEXT").await?;
+    return Ok(());
+}
+
+/// Adds a column to a table that was created by an older version of the server
+pub async fn add_column_if_missing(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    column_type: &str,
+) -> Result<(), Error> {
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+            .bind(table)
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+    if count == 0 {
+        query(&format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {column_type}"
+        ))
+        .execute(pool)
+        .await?;
+    }
     return Ok(());
 }
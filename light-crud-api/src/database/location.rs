@@ -12,6 +12,7 @@ use serde_json::{json, Value};
 use sqlx::FromRow;
 
 use crate::database::initialize::AppState;
+use crate::events::Event;
 
 const EXAMPLE_DATA: &str = r#"{"location":{"id":1,"x":24.0, "y": 12.0}}"#;
 const GET_SQL_STATEMENT: &str = "SELECT id, x, y FROM LED_Location WHERE id = ? LIMIT 1";
@@ -122,6 +123,11 @@ pub async fn delete_location_id(
         }
     };
 
+    state.events.publish(Event::EntityDeleted {
+        entity: "location",
+        id: frame_id as i64,
+    });
+
     return json!({"last insert rowid":data.last_insert_rowid()})
         .to_string()
         .into_response();
@@ -169,9 +175,13 @@ pub async fn put_location_id(
 
     match led_results {
         Ok(value) => {
+            state.events.publish(Event::EntityUpdated {
+                entity: "location",
+                id: led.id as i64,
+            });
             return json!({"result": format!("{value:?}")})
                 .to_string()
-                .into_response()
+                .into_response();
         }
         Err(value) => {
             return (
@@ -222,9 +232,13 @@ pub async fn post_location(
 
     match led_results {
         Ok(value) => {
+            state.events.publish(Event::EntityCreated {
+                entity: "location",
+                id: value.last_insert_rowid(),
+            });
             return json!({"result": format!("{value:?}")})
                 .to_string()
-                .into_response()
+                .into_response();
         }
         Err(value) => {
             return (
@@ -1,5 +1,31 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
 pub mod animation;
 pub mod frame;
 pub mod frame_data;
 pub mod initialize;
 pub mod location;
+pub mod playlist;
+pub mod schedule;
+
+/// Turns a database error into a response, a missing row is a 404 and a broken constraint is
+/// a bad request. `entity` names what was being stored for the messages, e.g. `playlist`.
+pub fn database_error(error: sqlx::Error, entity: &str) -> Response {
+    let (status_code, message) = match &error {
+        sqlx::Error::RowNotFound => (StatusCode::NOT_FOUND, error.to_string()),
+        sqlx::Error::Database(database_error) if database_error.is_foreign_key_violation() => (
+            StatusCode::BAD_REQUEST,
+            format!("the {entity} refers to an animation that does not exist"),
+        ),
+        sqlx::Error::Database(database_error) if database_error.is_unique_violation() => (
+            StatusCode::BAD_REQUEST,
+            format!("a {entity} with that name already exists"),
+        ),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+    };
+    return (status_code, json!({"error": message}).to_string()).into_response();
+}
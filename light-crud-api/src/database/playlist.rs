@@ -0,0 +1,569 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Router,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::executor::block_on;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::animation::{Animation, PlayOptions};
+use super::database_error;
+use super::initialize::AppState;
+use crate::config::LightsConfig;
+use crate::events::Event;
+use crate::lights::playback::{PlayRequest, PlaybackMode};
+use crate::lights::status::SharedStatus;
+use crate::lights::transition::TransitionKind;
+
+const EXAMPLE_DATA: &str = r#"
+{
+    "playlist":{
+        "name":"Evening",
+        "shuffle":false,
+        "repeat":true,
+        "entries":[
+            {"animation_id":1, "duration_ms":60000},
+            {"animation_id":2, "loop_count":3, "transition":"wipe", "transition_ms":2000}
+        ]
+    }
+}
+"#;
+
+/// How often a playing playlist checks whether the current entry has finished
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn new_id() -> i32 {
+    return -1;
+}
+
+#[derive(Clone, FromRow, Debug, Serialize, Deserialize)]
+pub struct Playlist {
+    #[serde(default = "new_id")]
+    pub id: i32,
+    pub name: String,
+    /// Play the entries in a random order, shuffled again on every pass
+    #[serde(default)]
+    pub shuffle: bool,
+    /// Go back to the start after the last entry
+    #[serde(default)]
+    pub repeat: bool,
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub entries: Vec<PlaylistEntry>,
+}
+
+/// One animation in a playlist, in the order given by `position`.
+///
+/// An entry moves on after `duration_ms` or once it has played through `loop_count` times,
+/// whichever comes first. With neither it plays through once.
+#[derive(Clone, FromRow, Debug, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    #[serde(default = "new_id")]
+    pub id: i32,
+    #[serde(default)]
+    pub playlist_id: i32,
+    #[serde(default)]
+    pub position: i32,
+    pub animation_id: i32,
+    pub duration_ms: Option<i64>,
+    pub loop_count: Option<i64>,
+    /// How to move into this entry, the config default if not given
+    pub transition: Option<TransitionKind>,
+    pub transition_ms: Option<i64>,
+}
+
+impl PlaylistEntry {
+    fn duration(&self) -> Option<Duration> {
+        return self
+            .duration_ms
+            .map(|milliseconds| Duration::from_millis(milliseconds.max(0) as u64));
+    }
+
+    /// Builds the request for the controller in the same way as playing the animation directly
+    pub fn to_request(&self, animation: Animation, lights: &LightsConfig) -> PlayRequest {
+        let mode = match (self.loop_count, self.duration_ms) {
+            (Some(_), _) => PlaybackMode::Repeat,
+            (None, Some(_)) => PlaybackMode::Loop,
+            (None, None) => PlaybackMode::OnceHold,
+        };
+        let options = PlayOptions {
+            transition: self.transition,
+            duration_ms: self
+                .transition_ms
+                .map(|milliseconds| milliseconds.max(0) as u64),
+            interpolation: None,
+            mode: Some(mode),
+            repeat: self.loop_count.map(|count| count.max(1) as u32),
+        };
+        return options.into_request(animation, lights);
+    }
+}
+
+impl Playlist {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err(String::from("playlist name can not be empty"));
+        }
+        for (position, entry) in self.entries.iter().enumerate() {
+            if entry.duration_ms.is_some_and(|duration| duration <= 0) {
+                return Err(format!("entry {position}: duration_ms must be more than 0"));
+            }
+            if entry.loop_count.is_some_and(|count| count <= 0) {
+                return Err(format!("entry {position}: loop_count must be more than 0"));
+            }
+            if entry.transition_ms.is_some_and(|duration| duration < 0) {
+                return Err(format!(
+                    "entry {position}: transition_ms can not be negative"
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    fn get_entries(id: i32, db: &Pool<Sqlite>) -> Result<Vec<PlaylistEntry>, sqlx::Error> {
+        return block_on(
+            sqlx::query_as::<_, PlaylistEntry>(
+                "SELECT id, playlist_id, position, animation_id, duration_ms, loop_count, transition, transition_ms FROM Playlist_Entries WHERE playlist_id = ? ORDER BY position",
+            )
+            .bind(id)
+            .fetch_all(db),
+        );
+    }
+
+    pub fn get_from_db(id: i32, db: &Pool<Sqlite>) -> Result<Self, sqlx::Error> {
+        let mut playlist = block_on(
+            sqlx::query_as::<_, Self>(
+                "SELECT id, name, shuffle, repeat FROM Playlists WHERE id = ?",
+            )
+            .bind(id)
+            .fetch_one(db),
+        )?;
+        playlist.entries = Self::get_entries(id, db)?;
+        return Ok(playlist);
+    }
+
+    pub fn get_all_from_db(db: &Pool<Sqlite>) -> Result<Vec<Self>, sqlx::Error> {
+        let mut playlists = block_on(
+            sqlx::query_as::<_, Self>("SELECT id, name, shuffle, repeat FROM Playlists")
+                .fetch_all(db),
+        )?;
+        for playlist in playlists.iter_mut() {
+            playlist.entries = Self::get_entries(playlist.id, db)?;
+        }
+        return Ok(playlists);
+    }
+
+    /// Writes the entries in the order they are listed, replacing any that were there before
+    async fn replace_entries(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM Playlist_Entries WHERE playlist_id = ?")
+            .bind(self.id)
+            .execute(&mut **transaction)
+            .await?;
+        for (position, entry) in self.entries.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO Playlist_Entries (playlist_id, position, animation_id, duration_ms, loop_count, transition, transition_ms) Values(?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(self.id)
+            .bind(position as i32)
+            .bind(entry.animation_id)
+            .bind(entry.duration_ms)
+            .bind(entry.loop_count)
+            .bind(entry.transition)
+            .bind(entry.transition_ms)
+            .execute(&mut **transaction)
+            .await?;
+        }
+        return Ok(());
+    }
+
+    pub fn insert_in_db(self: &Self, db: &Pool<Sqlite>) -> Result<Self, sqlx::Error> {
+        return block_on(async {
+            let mut transaction = db.begin().await?;
+            let result =
+                sqlx::query("INSERT INTO Playlists (name, shuffle, repeat) Values(?, ?, ?)")
+                    .bind(self.name.clone())
+                    .bind(self.shuffle)
+                    .bind(self.repeat)
+                    .execute(&mut *transaction)
+                    .await?;
+            let mut playlist = self.clone();
+            playlist.id = result.last_insert_rowid() as i32;
+            playlist.replace_entries(&mut transaction).await?;
+            transaction.commit().await?;
+            return Ok(playlist);
+        });
+    }
+
+    pub fn update_in_db(self: &Self, db: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
+        return block_on(async {
+            let mut transaction = db.begin().await?;
+            let result =
+                sqlx::query("UPDATE Playlists SET name = ?, shuffle = ?, repeat = ? WHERE id = ?")
+                    .bind(self.name.clone())
+                    .bind(self.shuffle)
+                    .bind(self.repeat)
+                    .bind(self.id)
+                    .execute(&mut *transaction)
+                    .await?;
+            if result.rows_affected() == 0 {
+                return Err(sqlx::Error::RowNotFound);
+            }
+            self.replace_entries(&mut transaction).await?;
+            transaction.commit().await?;
+            return Ok(());
+        });
+    }
+
+    pub fn delete_in_db(id: i32, db: &Pool<Sqlite>) -> Result<u64, sqlx::Error> {
+        return block_on(async {
+            let mut transaction = db.begin().await?;
+            let entries = sqlx::query("DELETE FROM Playlist_Entries WHERE playlist_id = ?")
+                .bind(id)
+                .execute(&mut *transaction)
+                .await?;
+            sqlx::query("DELETE FROM Playlists WHERE id = ?")
+                .bind(id)
+                .execute(&mut *transaction)
+                .await?;
+            transaction.commit().await?;
+            return Ok(entries.rows_affected());
+        });
+    }
+}
+
+/// Which playlist is playing and how far through it is
+#[derive(Clone, Debug, Serialize)]
+pub struct PlaylistProgress {
+    pub id: i32,
+    pub name: String,
+    /// Index into the playlist's entries of the one that is playing
+    pub entry: usize,
+    pub entry_count: usize,
+    pub animation_id: i32,
+    pub shuffle: bool,
+    pub repeat: bool,
+    /// Number of times the whole playlist has played through
+    pub passes: u32,
+}
+
+/// Optional query parameters when playing a playlist, overriding what is stored with it
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PlaylistOptions {
+    pub shuffle: Option<bool>,
+    pub repeat: Option<bool>,
+}
+
+/// Waits for the controller to pick up an entry sent at `sent_at` and play it out.
+///
+/// Returns false if something else started playing over the top of it. Time spent paused
+/// does not count towards the entry's duration.
+async fn wait_for_entry(entry: &PlaylistEntry, sent_at: Instant, status: &SharedStatus) -> bool {
+    let duration = entry.duration();
+    let mut started: Option<Instant> = None;
+    let mut played = Duration::ZERO;
+    let mut last_poll = Instant::now();
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let now = Instant::now();
+        let (animation_started, paused, finished) = {
+            let status = status.lock().unwrap();
+            (
+                status.player.animation_started,
+                status.player.paused,
+                status.player.finished,
+            )
+        };
+        match (started, animation_started) {
+            (None, Some(animation_started)) if animation_started >= sent_at => {
+                started = Some(animation_started);
+            }
+            (None, _) => {
+                last_poll = now;
+                continue;
+            }
+            (Some(ours), current) if current != Some(ours) => return false,
+            _ => {}
+        }
+        if !paused {
+            played += now.duration_since(last_poll);
+        }
+        last_poll = now;
+        if finished || duration.is_some_and(|duration| played >= duration) {
+            return true;
+        }
+    }
+}
+
+/// Plays the entries one after another through the controller, until the playlist ends or
+/// another animation is played over it
+async fn run_playlist(playlist: Playlist, state: Arc<AppState>) {
+    println!("Playlist: starting {} ({})", playlist.id, playlist.name);
+    let mut passes = 0;
+    'passes: loop {
+        let mut order: Vec<usize> = (0..playlist.entries.len()).collect();
+        if playlist.shuffle {
+            order.shuffle(&mut rand::thread_rng());
+        }
+        let mut played_any = false;
+        for index in order {
+            let entry = &playlist.entries[index];
+            let animation = match Animation::get_from_db(entry.animation_id, &state.db) {
+                Ok(animation) if !animation.frames.is_empty() => animation,
+                Ok(_) => {
+                    println!(
+                        "Playlist: animation {} has no frames, skipping it",
+                        entry.animation_id
+                    );
+                    continue;
+                }
+                Err(error) => {
+                    println!(
+                        "Playlist: could not load animation {}, skipping it: {error}",
+                        entry.animation_id
+                    );
+                    continue;
+                }
+            };
+            state.lights_status.lock().unwrap().playlist = Some(PlaylistProgress {
+                id: playlist.id,
+                name: playlist.name.clone(),
+                entry: index,
+                entry_count: playlist.entries.len(),
+                animation_id: entry.animation_id,
+                shuffle: playlist.shuffle,
+                repeat: playlist.repeat,
+                passes: passes,
+            });
+            let sent_at = Instant::now();
+            let request = entry.to_request(animation, &state.lights);
+            if state.send_to_controller.send(request).await.is_err() {
+                break 'passes;
+            }
+            if !wait_for_entry(entry, sent_at, &state.lights_status).await {
+                println!("Playlist: {} was interrupted", playlist.id);
+                break 'passes;
+            }
+            played_any = true;
+        }
+        passes += 1;
+        // a repeating playlist with nothing playable in it would spin forever
+        if !playlist.repeat || !played_any {
+            break;
+        }
+    }
+    state.lights_status.lock().unwrap().playlist = None;
+    println!("Playlist: finished {}", playlist.id);
+}
+
+/// Stops the playlist that is playing, if there is one. Whatever it was showing keeps playing.
+pub fn stop_running_playlist(state: &AppState) -> bool {
+    let running = state.playlist_task.lock().unwrap().take();
+    state.lights_status.lock().unwrap().playlist = None;
+    return match running {
+        Some((_, task)) if !task.is_finished() => {
+            task.abort();
+            true
+        }
+        _ => false,
+    };
+}
+
+/// The id of the playlist that is playing and the task feeding it to the controller
+pub type SharedPlaylistTask = Arc<Mutex<Option<(i32, JoinHandle<()>)>>>;
+
+/// Id of the playlist that is playing, if there is one
+pub fn running_playlist(state: &AppState) -> Option<i32> {
+    return match state.playlist_task.lock().unwrap().as_ref() {
+        Some((id, task)) if !task.is_finished() => Some(*id),
+        _ => None,
+    };
+}
+
+/// Starts feeding the playlist to the controller, replacing any playlist already playing
+pub fn start_playlist(state: &Arc<AppState>, playlist: Playlist) {
+    stop_running_playlist(state);
+    let id = playlist.id;
+    let task = tokio::spawn(run_playlist(playlist, state.clone()));
+    *state.playlist_task.lock().unwrap() = Some((id, task));
+}
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/", post(post_playlist))
+        .route("/", get(get_all_playlists))
+        .route("/stop", post(stop_playlist))
+        .route("/:id", get(get_playlist_id))
+        .route("/:id", put(put_playlist_id))
+        .route("/:id", delete(delete_playlist_id))
+        .route("/:id/play", post(play_playlist_id))
+        .with_state(state);
+
+    index.insert("/playlist", "GET,POST");
+    index.insert("/playlist/:id", "GET,PUT,DELETE");
+    index.insert("/playlist/:id/play", "POST");
+    index.insert("/playlist/stop", "POST");
+    return app;
+}
+
+fn extract_playlist(payload: &str) -> Result<Playlist, Response> {
+    let json_payload: Value = match serde_json::from_str(payload) {
+        Ok(result) => result,
+        Err(error) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                json!({"error":format!("{error:?}"), "example":EXAMPLE_DATA}).to_string(),
+            )
+                .into_response());
+        }
+    };
+    let playlist_dict = match json_payload.get("playlist") {
+        Some(value) => value.clone(),
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                json!({"error":"playlist not found", "example":EXAMPLE_DATA}).to_string(),
+            )
+                .into_response())
+        }
+    };
+    let playlist: Playlist = match serde_json::from_value(playlist_dict) {
+        Ok(value) => value,
+        Err(error) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                json!({"error":error.to_string(), "example":EXAMPLE_DATA}).to_string(),
+            )
+                .into_response())
+        }
+    };
+    if let Err(error) = playlist.validate() {
+        return Err((StatusCode::BAD_REQUEST, json!({"error":error}).to_string()).into_response());
+    }
+    return Ok(playlist);
+}
+
+pub async fn post_playlist(State(state): State<Arc<AppState>>, payload: String) -> Response {
+    let playlist = match extract_playlist(&payload) {
+        Ok(value) => value,
+        Err(response) => return response,
+    };
+    let stored = playlist
+        .insert_in_db(&state.db)
+        .and_then(|inserted| Playlist::get_from_db(inserted.id, &state.db));
+    return match stored {
+        Ok(value) => {
+            state.events.publish(Event::EntityCreated {
+                entity: "playlist",
+                id: value.id as i64,
+            });
+            json!({"playlist": value}).to_string().into_response()
+        }
+        Err(error) => database_error(error, "playlist"),
+    };
+}
+
+pub async fn get_all_playlists(State(state): State<Arc<AppState>>) -> Response {
+    return match Playlist::get_all_from_db(&state.db) {
+        Ok(value) => serde_json::to_string(&value).unwrap().into_response(),
+        Err(error) => database_error(error, "playlist"),
+    };
+}
+
+pub async fn get_playlist_id(Path(id): Path<i32>, State(state): State<Arc<AppState>>) -> Response {
+    return match Playlist::get_from_db(id, &state.db) {
+        Ok(value) => serde_json::to_string(&value).unwrap().into_response(),
+        Err(error) => database_error(error, "playlist"),
+    };
+}
+
+pub async fn put_playlist_id(
+    Path(id): Path<i32>,
+    State(state): State<Arc<AppState>>,
+    payload: String,
+) -> Response {
+    let mut playlist = match extract_playlist(&payload) {
+        Ok(value) => value,
+        Err(response) => return response,
+    };
+    playlist.id = id;
+    let stored = playlist
+        .update_in_db(&state.db)
+        .and_then(|_| Playlist::get_from_db(id, &state.db));
+    return match stored {
+        Ok(value) => {
+            state.events.publish(Event::EntityUpdated {
+                entity: "playlist",
+                id: id as i64,
+            });
+            json!({"playlist": value}).to_string().into_response()
+        }
+        Err(error) => database_error(error, "playlist"),
+    };
+}
+
+pub async fn delete_playlist_id(
+    Path(id): Path<i32>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    return match Playlist::delete_in_db(id, &state.db) {
+        Ok(entries) => {
+            if running_playlist(&state) == Some(id) {
+                stop_running_playlist(&state);
+            }
+            state.events.publish(Event::EntityDeleted {
+                entity: "playlist",
+                id: id as i64,
+            });
+            json!({"entries_deleted": entries})
+                .to_string()
+                .into_response()
+        }
+        Err(error) => database_error(error, "playlist"),
+    };
+}
+
+/// Starts playing a playlist from its first entry, replacing any playlist already playing.
+/// `?shuffle=true&repeat=false` override what is stored with the playlist.
+pub async fn play_playlist_id(
+    Path(id): Path<i32>,
+    Query(options): Query<PlaylistOptions>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let mut playlist = match Playlist::get_from_db(id, &state.db) {
+        Ok(value) => value,
+        Err(error) => return database_error(error, "playlist"),
+    };
+    if playlist.entries.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            json!({"error": format!("playlist {id} has no entries")}).to_string(),
+        )
+            .into_response();
+    }
+    playlist.shuffle = options.shuffle.unwrap_or(playlist.shuffle);
+    playlist.repeat = options.repeat.unwrap_or(playlist.repeat);
+
+    start_playlist(&state, playlist.clone());
+    return json!({"playlist": playlist}).to_string().into_response();
+}
+
+pub async fn stop_playlist(State(state): State<Arc<AppState>>) -> Response {
+    let stopped = stop_running_playlist(&state);
+    return json!({"stopped": stopped}).to_string().into_response();
+}
@@ -0,0 +1,911 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Router,
+};
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, NaiveTime, TimeZone};
+use futures::executor::block_on;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::animation::Animation;
+use super::database_error;
+use super::initialize::AppState;
+use super::playlist::{start_playlist, stop_running_playlist, Playlist};
+use crate::config::LightsConfig;
+use crate::events::Event;
+use crate::lights::brightness::{BrightnessRequest, BrightnessTarget, Easing};
+use crate::lights::playback::PlayRequest;
+use crate::lights::player::PlayerCommand;
+use crate::solar::{SolarCalculator, SolarEvent};
+use crate::thread_utils::NotifyChecker;
+
+const EXAMPLE_DATA: &str = r#"
+{
+    "schedule":{
+        "name":"Evening brightness",
+        "time_of_day":"16:00",
+        "weekdays":127,
+        "action":"set_brightness",
+        "value":100
+    }
+}
+or
+{
+    "schedule":{
+        "name":"Bedtime",
+        "time_of_day":"22:30",
+        "action":"set_brightness",
+        "value":5,
+        "ramp_ms":1800000,
+        "curve":"ease_out"
+    }
+}
+or
+{
+    "schedule":{
+        "name":"Porch at dusk",
+        "solar_event":"civil_dusk",
+        "action":"set_brightness",
+        "channel":"entryway",
+        "value":200
+    }
+}
+or
+{
+    "schedule":{
+        "name":"Weeknight show",
+        "cron":"30 18 * * Mon-Fri",
+        "action":"play_playlist",
+        "value":1
+    }
+}
+or
+{
+    "schedule":{
+        "name":"Before sunset",
+        "solar_event":"sunset",
+        "offset_minutes":-20,
+        "action":"play_animation",
+        "value":2
+    }
+}
+"#;
+
+/// Longest the scheduler sleeps before checking for shutdown
+const MAX_SLEEP: Duration = Duration::from_secs(1);
+
+/// Every day of the week in `Schedule::weekdays`
+const ALL_WEEKDAYS: i64 = 0b111_1111;
+
+/// What a schedule does when it fires
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ScheduleAction {
+    /// Set `channel`, or every channel when there is none, to `value`
+    SetBrightness,
+    /// Set the master brightness every channel is scaled by to `value`
+    SetMasterBrightness,
+    /// Play the animation with the id `value`
+    PlayAnimation,
+    /// Play the playlist with the id `value`
+    PlayPlaylist,
+    /// Fade the lights out, the same as `/player/stop`
+    Off,
+}
+
+impl ScheduleAction {
+    fn sets_brightness(&self) -> bool {
+        return matches!(
+            self,
+            ScheduleAction::SetBrightness | ScheduleAction::SetMasterBrightness
+        );
+    }
+}
+
+/// What firing a schedule changes
+#[derive(Debug, PartialEq)]
+enum Controls<'a> {
+    Playback,
+    /// The level of one channel, or every channel when None
+    Brightness(Option<&'a str>),
+    MasterBrightness,
+}
+
+fn enabled() -> bool {
+    return true;
+}
+
+/// A stored schedule entry. It fires either on a cron expression, at a time of day, or
+/// relative to sunrise or sunset, on the days picked out by `weekdays`. Times are local.
+#[derive(Clone, FromRow, Debug, Serialize, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub id: i32,
+    pub name: String,
+    /// Standard five field cron expression, or six with seconds first
+    pub cron: Option<String>,
+    /// "HH:MM" or "HH:MM:SS"
+    pub time_of_day: Option<String>,
+    /// Needs a `[location]` in the config
+    pub solar_event: Option<SolarEvent>,
+    /// Minutes after `solar_event`, negative for before
+    pub offset_minutes: Option<i64>,
+    /// Days `time_of_day` and `solar_event` apply to, bit 0 for Monday through to bit 6 for
+    /// Sunday
+    pub weekdays: Option<i64>,
+    pub action: ScheduleAction,
+    /// The brightness when setting the brightness, otherwise the id of the animation or playlist
+    pub value: Option<i64>,
+    /// Name of the channel `set_brightness` applies to, leave out for every channel
+    pub channel: Option<String>,
+    /// How long the brightness takes to fade to `value`, leave out to jump straight to it
+    pub ramp_ms: Option<i64>,
+    pub curve: Option<Easing>,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(skip_deserializing)]
+    pub last_fired: Option<String>,
+    #[sqlx(skip)]
+    #[serde(skip_deserializing)]
+    pub next_fire: Option<String>,
+}
+
+/// When a schedule fires, parsed from the stored fields
+#[derive(Clone, Debug)]
+pub enum Trigger {
+    Cron(cron::Schedule),
+    Daily {
+        time: NaiveTime,
+        weekdays: u8,
+    },
+    Solar {
+        event: SolarEvent,
+        offset: chrono::Duration,
+        weekdays: u8,
+        calculator: SolarCalculator,
+    },
+}
+
+fn parse_cron(expression: &str) -> Result<cron::Schedule, String> {
+    // the cron crate wants seconds as the first field
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {expression}"),
+        _ => expression.to_string(),
+    };
+    return cron::Schedule::from_str(&expression)
+        .map_err(|error| format!("invalid cron expression {expression:?}: {error}"));
+}
+
+fn parse_time_of_day(time: &str) -> Result<NaiveTime, String> {
+    return NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .map_err(|_| format!("invalid time_of_day {time:?}, expected HH:MM"));
+}
+
+fn parse_weekdays(weekdays: Option<i64>) -> Result<u8, String> {
+    let weekdays = weekdays.unwrap_or(ALL_WEEKDAYS);
+    if weekdays <= 0 || weekdays > ALL_WEEKDAYS {
+        return Err(format!(
+            "weekdays must be a mask of 1 to {ALL_WEEKDAYS}, bit 0 for Monday"
+        ));
+    }
+    return Ok(weekdays as u8);
+}
+
+fn includes_day(weekdays: u8, date: NaiveDate) -> bool {
+    return weekdays & (1 << date.weekday().num_days_from_monday()) != 0;
+}
+
+impl Trigger {
+    /// When the trigger fires for `date`, None if it does not fire that day
+    fn on_date(&self, date: NaiveDate) -> Option<DateTime<Local>> {
+        match self {
+            Trigger::Cron(_) => None,
+            Trigger::Daily { time, weekdays } if includes_day(*weekdays, date) => {
+                // a time skipped by daylight saving does not happen that day
+                Local.from_local_datetime(&date.and_time(*time)).earliest()
+            }
+            Trigger::Solar {
+                event,
+                offset,
+                weekdays,
+                calculator,
+            } if includes_day(*weekdays, date) => calculator
+                .event_on(date, *event)
+                .map(|at| (at + *offset).with_timezone(&Local)),
+            _ => None,
+        }
+    }
+
+    /// The day at the lights that `instant` falls on
+    fn date_of(&self, instant: &DateTime<Local>) -> NaiveDate {
+        match self {
+            Trigger::Solar { calculator, .. } => calculator.date_of(instant),
+            _ => instant.date_naive(),
+        }
+    }
+
+    pub fn next_after(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+        if let Trigger::Cron(schedule) = self {
+            return schedule.after(after).next();
+        }
+        // an offset can push a solar trigger into the day before or after, so look a day
+        // either side of the week
+        let start = self.date_of(after).checked_sub_days(Days::new(1))?;
+        return (0..=9)
+            .filter_map(|offset| start.checked_add_days(Days::new(offset)))
+            .filter_map(|date| self.on_date(date))
+            .find(|fires_at| fires_at > after);
+    }
+
+    /// The most recent time it fired, up to `until`
+    pub fn latest_until(&self, until: &DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Trigger::Cron(schedule) => {
+                // the cron crate only looks before the whole second it is given
+                let after = *until + chrono::Duration::seconds(1);
+                schedule
+                    .after(&after)
+                    .rev()
+                    .find(|fired_at| fired_at <= until)
+            }
+            _ => {
+                let start = self.date_of(until).checked_add_days(Days::new(1))?;
+                (0..=9)
+                    .filter_map(|offset| start.checked_sub_days(Days::new(offset)))
+                    .filter_map(|date| self.on_date(date))
+                    .find(|fired_at| fired_at <= until)
+            }
+        }
+    }
+
+    /// The next `count` times it will fire after `after`
+    pub fn upcoming(&self, after: &DateTime<Local>, count: usize) -> Vec<DateTime<Local>> {
+        let mut upcoming = Vec::new();
+        let mut after = *after;
+        while upcoming.len() < count {
+            match self.next_after(&after) {
+                Some(fires_at) => {
+                    upcoming.push(fires_at);
+                    after = fires_at;
+                }
+                None => break,
+            }
+        }
+        return upcoming;
+    }
+}
+
+impl Schedule {
+    /// `solar` is None when there is no location in the config
+    pub fn trigger(&self, solar: Option<&SolarCalculator>) -> Result<Trigger, String> {
+        if self.offset_minutes.is_some() && self.solar_event.is_none() {
+            return Err(String::from("offset_minutes only applies to solar_event"));
+        }
+        match (&self.cron, &self.time_of_day, self.solar_event) {
+            (Some(expression), None, None) => return Ok(Trigger::Cron(parse_cron(expression)?)),
+            (None, Some(time), None) => {
+                return Ok(Trigger::Daily {
+                    time: parse_time_of_day(time)?,
+                    weekdays: parse_weekdays(self.weekdays)?,
+                });
+            }
+            (None, None, Some(event)) => {
+                let calculator = match solar {
+                    Some(calculator) => calculator.clone(),
+                    None => return Err(String::from(
+                        "solar_event needs a [location] with latitude and longitude in the config",
+                    )),
+                };
+                return Ok(Trigger::Solar {
+                    event: event,
+                    offset: chrono::Duration::minutes(self.offset_minutes.unwrap_or(0)),
+                    weekdays: parse_weekdays(self.weekdays)?,
+                    calculator: calculator,
+                });
+            }
+            _ => return Err(String::from("give one of cron, time_of_day or solar_event")),
+        }
+    }
+
+    /// When the scheduler last acted on it, which is kept over a restart
+    fn last_fired_at(&self) -> Option<DateTime<Local>> {
+        return self
+            .last_fired
+            .as_deref()
+            .and_then(|fired_at| DateTime::parse_from_rfc3339(fired_at).ok())
+            .map(|fired_at| fired_at.with_timezone(&Local));
+    }
+
+    /// What firing the schedule changes. A later schedule that changes the same thing
+    /// overrules an earlier one, e.g. brightness and what is playing are set independently.
+    fn controls(&self) -> Controls<'_> {
+        return match self.action {
+            ScheduleAction::SetBrightness => Controls::Brightness(self.channel.as_deref()),
+            ScheduleAction::SetMasterBrightness => Controls::MasterBrightness,
+            _ => Controls::Playback,
+        };
+    }
+
+    pub fn validate(
+        &self,
+        solar: Option<&SolarCalculator>,
+        lights: &LightsConfig,
+    ) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err(String::from("schedule name can not be empty"));
+        }
+        self.trigger(solar)?;
+        match (self.action, self.value) {
+            (ScheduleAction::Off, _) => {}
+            (action, Some(value)) if action.sets_brightness() && (0..=255).contains(&value) => {}
+            (action, _) if action.sets_brightness() => {
+                return Err(format!("{action:?} needs a value from 0 to 255"))
+            }
+            (_, None) => return Err(format!("{:?} needs the id to play as value", self.action)),
+            (_, Some(_)) => {}
+        }
+        if !self.action.sets_brightness() && (self.ramp_ms.is_some() || self.curve.is_some()) {
+            return Err(String::from(
+                "ramp_ms and curve only apply to setting the brightness",
+            ));
+        }
+        match (&self.channel, self.action) {
+            (None, _) => {}
+            (Some(channel), ScheduleAction::SetBrightness) => {
+                if lights.channel_index(channel).is_none() {
+                    return Err(format!("there is no channel {channel:?} in the config"));
+                }
+            }
+            (Some(_), _) => return Err(String::from("channel only applies to set_brightness")),
+        }
+        if self.ramp_ms.is_some_and(|ramp_ms| ramp_ms < 0) {
+            return Err(String::from("ramp_ms can not be negative"));
+        }
+        return Ok(());
+    }
+
+    /// Fills in `next_fire` for reporting
+    pub fn with_next_fire(
+        mut self,
+        now: &DateTime<Local>,
+        solar: Option<&SolarCalculator>,
+    ) -> Self {
+        self.next_fire = match (self.enabled, self.trigger(solar)) {
+            (true, Ok(trigger)) => trigger.next_after(now).map(|at| at.to_rfc3339()),
+            _ => None,
+        };
+        return self;
+    }
+
+    pub fn get_from_db(id: i32, db: &Pool<Sqlite>) -> Result<Self, sqlx::Error> {
+        return block_on(
+            sqlx::query_as::<_, Self>(
+                "SELECT id, name, cron, time_of_day, solar_event, offset_minutes, weekdays, action, value, channel, ramp_ms, curve, enabled, last_fired FROM Schedules WHERE id = ?",
+            )
+            .bind(id)
+            .fetch_one(db),
+        );
+    }
+
+    pub fn get_all_from_db(db: &Pool<Sqlite>) -> Result<Vec<Self>, sqlx::Error> {
+        return block_on(
+            sqlx::query_as::<_, Self>(
+                "SELECT id, name, cron, time_of_day, solar_event, offset_minutes, weekdays, action, value, channel, ramp_ms, curve, enabled, last_fired FROM Schedules",
+            )
+            .fetch_all(db),
+        );
+    }
+
+    pub fn insert_in_db(self: &Self, db: &Pool<Sqlite>) -> Result<Self, sqlx::Error> {
+        let result = block_on(
+            sqlx::query(
+                "INSERT INTO Schedules (name, cron, time_of_day, solar_event, offset_minutes, weekdays, action, value, channel, ramp_ms, curve, enabled) Values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(self.name.clone())
+            .bind(self.cron.clone())
+            .bind(self.time_of_day.clone())
+            .bind(self.solar_event)
+            .bind(self.offset_minutes)
+            .bind(self.weekdays)
+            .bind(self.action)
+            .bind(self.value)
+            .bind(&self.channel)
+            .bind(self.ramp_ms)
+            .bind(self.curve)
+            .bind(self.enabled)
+            .execute(db),
+        )?;
+        return Self::get_from_db(result.last_insert_rowid() as i32, db);
+    }
+
+    pub fn update_in_db(self: &Self, db: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
+        let result = block_on(
+            sqlx::query(
+                "UPDATE Schedules SET name = ?, cron = ?, time_of_day = ?, solar_event = ?, offset_minutes = ?, weekdays = ?, action = ?, value = ?, channel = ?, ramp_ms = ?, curve = ?, enabled = ? WHERE id = ?",
+            )
+            .bind(self.name.clone())
+            .bind(self.cron.clone())
+            .bind(self.time_of_day.clone())
+            .bind(self.solar_event)
+            .bind(self.offset_minutes)
+            .bind(self.weekdays)
+            .bind(self.action)
+            .bind(self.value)
+            .bind(&self.channel)
+            .bind(self.ramp_ms)
+            .bind(self.curve)
+            .bind(self.enabled)
+            .bind(self.id)
+            .execute(db),
+        )?;
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        return Ok(());
+    }
+
+    pub fn delete_in_db(id: i32, db: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
+        let result = block_on(
+            sqlx::query("DELETE FROM Schedules WHERE id = ?")
+                .bind(id)
+                .execute(db),
+        )?;
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        return Ok(());
+    }
+
+    fn set_last_fired(id: i32, fired_at: &DateTime<Local>, db: &Pool<Sqlite>) {
+        let result = block_on(
+            sqlx::query("UPDATE Schedules SET last_fired = ? WHERE id = ?")
+                .bind(fired_at.to_rfc3339())
+                .bind(id)
+                .execute(db),
+        );
+        if let Err(error) = result {
+            println!("Scheduler: could not record schedule {id} firing: {error}");
+        }
+    }
+}
+
+/// Loads the enabled schedules, skipping any that can not be parsed
+fn load_schedules(state: &AppState) -> Vec<(Schedule, Trigger)> {
+    let schedules = match Schedule::get_all_from_db(&state.db) {
+        Ok(value) => value,
+        Err(error) => {
+            println!("Scheduler: could not load schedules: {error}");
+            return Vec::new();
+        }
+    };
+    return schedules
+        .into_iter()
+        .filter(|schedule| schedule.enabled)
+        .filter_map(|schedule| match schedule.trigger(state.solar.as_ref()) {
+            Ok(trigger) => Some((schedule, trigger)),
+            Err(error) => {
+                println!("Scheduler: skipping schedule {}: {error}", schedule.id);
+                None
+            }
+        })
+        .collect();
+}
+
+/// The latest schedule to have fired for each brightness level and for what is playing, for
+/// those that fired after `last_check` and are still in effect at `now`.
+///
+/// Only the latest schedule in each group matters, so an older one that never fired can not
+/// undo a newer one. On start up there is no `last_check`, so each schedule is checked against
+/// its stored `last_fired` instead, and a restart does not repeat what already happened.
+/// Brightness levels are not kept over a restart, so those are always put back on start up.
+fn due<'a>(
+    schedules: &'a [(Schedule, Trigger)],
+    last_check: Option<DateTime<Local>>,
+    now: &DateTime<Local>,
+) -> Vec<(DateTime<Local>, &'a Schedule)> {
+    let mut latest: Vec<(DateTime<Local>, &Schedule)> = Vec::new();
+    for (schedule, trigger) in schedules {
+        let fired_at = match trigger.latest_until(now) {
+            Some(value) => value,
+            None => continue,
+        };
+        let controls = schedule.controls();
+        match latest
+            .iter_mut()
+            .find(|(_, other)| other.controls() == controls)
+        {
+            Some(entry) if entry.0 <= fired_at => *entry = (fired_at, schedule),
+            Some(_) => {}
+            None => latest.push((fired_at, schedule)),
+        }
+    }
+    latest.retain(|(fired_at, schedule)| {
+        let since = match last_check {
+            Some(last_check) => Some(last_check),
+            None if schedule.action.sets_brightness() => None,
+            None => schedule.last_fired_at(),
+        };
+        return since.is_none_or(|since| *fired_at > since);
+    });
+    latest.sort_by_key(|(fired_at, _)| *fired_at);
+    return latest;
+}
+
+async fn fire(schedule: &Schedule, state: &Arc<AppState>) -> Result<(), String> {
+    let value = schedule.value.unwrap_or_default();
+    match schedule.action {
+        ScheduleAction::SetBrightness | ScheduleAction::SetMasterBrightness => {
+            let target = match (schedule.action, &schedule.channel) {
+                (ScheduleAction::SetMasterBrightness, _) => BrightnessTarget::Master,
+                (_, None) => BrightnessTarget::AllChannels,
+                (_, Some(channel)) => match state.lights.channel_index(channel) {
+                    Some(index) => BrightnessTarget::Channel(index),
+                    None => return Err(format!("there is no channel {channel:?} in the config")),
+                },
+            };
+            let request = BrightnessRequest {
+                target: target,
+                value: value.clamp(0, 255) as u8,
+                duration: Duration::from_millis(schedule.ramp_ms.unwrap_or(0).max(0) as u64),
+                easing: schedule.curve.unwrap_or_default(),
+            };
+            state
+                .send_to_brightness
+                .send(request)
+                .await
+                .map_err(|error| error.to_string())?;
+        }
+        ScheduleAction::PlayAnimation => {
+            let animation = Animation::get_from_db(value as i32, &state.db)
+                .map_err(|error| format!("animation {value}: {error}"))?;
+            stop_running_playlist(state);
+            state
+                .send_to_controller
+                .send(PlayRequest::new(animation, &state.lights))
+                .await
+                .map_err(|error| error.to_string())?;
+        }
+        ScheduleAction::PlayPlaylist => {
+            let playlist = Playlist::get_from_db(value as i32, &state.db)
+                .map_err(|error| format!("playlist {value}: {error}"))?;
+            start_playlist(state, playlist);
+        }
+        ScheduleAction::Off => {
+            stop_running_playlist(state);
+            state
+                .send_to_player
+                .send(PlayerCommand::Stop)
+                .await
+                .map_err(|error| error.to_string())?;
+        }
+    }
+    return Ok(());
+}
+
+/// Acts on schedules as their times come round.
+///
+/// Nothing is resent while a schedule stays in effect. On start up the latest schedules that
+/// fired while the server was down are acted on once, so the lights come back in the state they
+/// should be in, see [`due`].
+pub async fn run_scheduler(state: Arc<AppState>, shutdown: NotifyChecker) {
+    println!("Scheduler: Starting");
+    let mut schedules = load_schedules(&state);
+    let mut last_check: Option<DateTime<Local>> = None;
+    while !shutdown.is_notified() {
+        let now = Local::now();
+        for (fired_at, schedule) in due(&schedules, last_check, &now) {
+            println!(
+                "Scheduler: {} ({}) {:?} {:?}",
+                schedule.id, schedule.name, schedule.action, schedule.value
+            );
+            match fire(schedule, &state).await {
+                Ok(()) => state.events.publish(Event::ScheduleFired {
+                    id: schedule.id,
+                    name: schedule.name.clone(),
+                    action: schedule.action,
+                    value: schedule.value,
+                }),
+                Err(error) => println!("Scheduler: schedule {} failed: {error}", schedule.id),
+            }
+            Schedule::set_last_fired(schedule.id, &fired_at, &state.db);
+        }
+        last_check = Some(now);
+
+        let next = schedules
+            .iter()
+            .filter_map(|(_, trigger)| trigger.next_after(&now))
+            .min();
+        let sleep_for = next.map_or(MAX_SLEEP, |next| {
+            (next - Local::now())
+                .to_std()
+                .unwrap_or_default()
+                .min(MAX_SLEEP)
+        });
+        tokio::select! {
+            _ = tokio::time::sleep(sleep_for) => {}
+            _ = state.schedules_changed.notified() => {
+                schedules = load_schedules(&state);
+            }
+        }
+    }
+    println!("Scheduler: Stopped");
+}
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/", post(post_schedule))
+        .route("/", get(get_all_schedules))
+        .route("/upcoming", get(get_upcoming))
+        .route("/:id", get(get_schedule_id))
+        .route("/:id", put(put_schedule_id))
+        .route("/:id", delete(delete_schedule_id))
+        .with_state(state);
+
+    index.insert("/schedule", "GET,POST");
+    index.insert("/schedule/:id", "GET,PUT,DELETE");
+    index.insert("/schedule/upcoming", "GET");
+    return app;
+}
+
+fn extract_schedule(payload: &str, state: &AppState) -> Result<Schedule, Response> {
+    let json_payload: Value = match serde_json::from_str(payload) {
+        Ok(result) => result,
+        Err(error) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                json!({"error":format!("{error:?}"), "example":EXAMPLE_DATA}).to_string(),
+            )
+                .into_response());
+        }
+    };
+    let schedule_dict = match json_payload.get("schedule") {
+        Some(value) => value.clone(),
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                json!({"error":"schedule not found", "example":EXAMPLE_DATA}).to_string(),
+            )
+                .into_response())
+        }
+    };
+    let schedule: Schedule = match serde_json::from_value(schedule_dict) {
+        Ok(value) => value,
+        Err(error) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                json!({"error":error.to_string(), "example":EXAMPLE_DATA}).to_string(),
+            )
+                .into_response())
+        }
+    };
+    if let Err(error) = schedule.validate(state.solar.as_ref(), &state.lights) {
+        return Err((StatusCode::BAD_REQUEST, json!({"error":error}).to_string()).into_response());
+    }
+    return Ok(schedule);
+}
+
+pub async fn post_schedule(State(state): State<Arc<AppState>>, payload: String) -> Response {
+    let schedule = match extract_schedule(&payload, &state) {
+        Ok(value) => value,
+        Err(response) => return response,
+    };
+    return match schedule.insert_in_db(&state.db) {
+        Ok(value) => {
+            state.schedules_changed.notify_one();
+            state.events.publish(Event::EntityCreated {
+                entity: "schedule",
+                id: value.id as i64,
+            });
+            json!({"schedule": value.with_next_fire(&Local::now(), state.solar.as_ref())})
+                .to_string()
+                .into_response()
+        }
+        Err(error) => database_error(error, "schedule"),
+    };
+}
+
+pub async fn get_all_schedules(State(state): State<Arc<AppState>>) -> Response {
+    let now = Local::now();
+    return match Schedule::get_all_from_db(&state.db) {
+        Ok(value) => {
+            let schedules: Vec<Schedule> = value
+                .into_iter()
+                .map(|schedule| schedule.with_next_fire(&now, state.solar.as_ref()))
+                .collect();
+            serde_json::to_string(&schedules).unwrap().into_response()
+        }
+        Err(error) => database_error(error, "schedule"),
+    };
+}
+
+pub async fn get_schedule_id(Path(id): Path<i32>, State(state): State<Arc<AppState>>) -> Response {
+    return match Schedule::get_from_db(id, &state.db) {
+        Ok(value) => {
+            serde_json::to_string(&value.with_next_fire(&Local::now(), state.solar.as_ref()))
+                .unwrap()
+                .into_response()
+        }
+        Err(error) => database_error(error, "schedule"),
+    };
+}
+
+pub async fn put_schedule_id(
+    Path(id): Path<i32>,
+    State(state): State<Arc<AppState>>,
+    payload: String,
+) -> Response {
+    let mut schedule = match extract_schedule(&payload, &state) {
+        Ok(value) => value,
+        Err(response) => return response,
+    };
+    schedule.id = id;
+    let stored = schedule
+        .update_in_db(&state.db)
+        .and_then(|_| Schedule::get_from_db(id, &state.db));
+    return match stored {
+        Ok(value) => {
+            state.schedules_changed.notify_one();
+            state.events.publish(Event::EntityUpdated {
+                entity: "schedule",
+                id: id as i64,
+            });
+            json!({"schedule": value.with_next_fire(&Local::now(), state.solar.as_ref())})
+                .to_string()
+                .into_response()
+        }
+        Err(error) => database_error(error, "schedule"),
+    };
+}
+
+pub async fn delete_schedule_id(
+    Path(id): Path<i32>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    return match Schedule::delete_in_db(id, &state.db) {
+        Ok(_) => {
+            state.schedules_changed.notify_one();
+            state.events.publish(Event::EntityDeleted {
+                entity: "schedule",
+                id: id as i64,
+            });
+            json!({"deleted": id}).to_string().into_response()
+        }
+        Err(error) => database_error(error, "schedule"),
+    };
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpcomingOptions {
+    pub count: Option<usize>,
+}
+
+/// Returns the next planned firings across every enabled schedule, soonest first.
+/// `?count=` sets how many, 10 by default.
+pub async fn get_upcoming(
+    Query(options): Query<UpcomingOptions>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let count = options.count.unwrap_or(10).min(1000);
+    let now = Local::now();
+    let mut upcoming: Vec<(DateTime<Local>, Schedule)> = load_schedules(&state)
+        .into_iter()
+        .flat_map(|(schedule, trigger)| {
+            trigger
+                .upcoming(&now, count)
+                .into_iter()
+                .map(move |fires_at| (fires_at, schedule.clone()))
+        })
+        .collect();
+    upcoming.sort_by_key(|(fires_at, _)| *fires_at);
+    let upcoming: Vec<Value> = upcoming
+        .into_iter()
+        .take(count)
+        .map(|(fires_at, schedule)| {
+            json!({
+                "at": fires_at.to_rfc3339(),
+                "schedule_id": schedule.id,
+                "name": schedule.name,
+                "action": schedule.action,
+                "value": schedule.value,
+            })
+        })
+        .collect();
+    return serde_json::to_string(&upcoming).unwrap().into_response();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(
+        id: i32,
+        time_of_day: &str,
+        action: &str,
+        last_fired: Option<&str>,
+    ) -> (Schedule, Trigger) {
+        let mut schedule: Schedule = serde_json::from_value(json!({
+            "name": format!("schedule {id}"),
+            "time_of_day": time_of_day,
+            "action": action,
+            "value": 1,
+        }))
+        .unwrap();
+        schedule.id = id;
+        schedule.last_fired = last_fired.map(|fired_at| at(fired_at).to_rfc3339());
+        let trigger = schedule.trigger(None).unwrap();
+        return (schedule, trigger);
+    }
+
+    fn at(time: &str) -> DateTime<Local> {
+        let time = NaiveDate::from_ymd_opt(2024, 1, 15)
+            .unwrap()
+            .and_time(parse_time_of_day(time).unwrap());
+        return Local.from_local_datetime(&time).unwrap();
+    }
+
+    fn ids(due: Vec<(DateTime<Local>, &Schedule)>) -> Vec<i32> {
+        return due.into_iter().map(|(_, schedule)| schedule.id).collect();
+    }
+
+    #[test]
+    fn start_up_does_not_repeat_what_already_fired() {
+        let schedules = vec![
+            schedule(1, "18:00", "play_animation", Some("18:00")),
+            schedule(2, "23:00", "off", Some("23:00")),
+        ];
+        assert_eq!(ids(due(&schedules, None, &at("23:30"))), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn start_up_fires_what_was_missed() {
+        let schedules = vec![
+            schedule(1, "18:00", "play_animation", Some("18:00")),
+            schedule(2, "23:00", "off", None),
+        ];
+        assert_eq!(ids(due(&schedules, None, &at("23:30"))), vec![2]);
+    }
+
+    #[test]
+    fn an_older_schedule_does_not_undo_a_newer_one() {
+        // the animation was added after 18:00 so has never fired, but the lights went off since
+        let schedules = vec![
+            schedule(1, "18:00", "play_animation", None),
+            schedule(2, "23:00", "off", Some("23:00")),
+        ];
+        assert_eq!(ids(due(&schedules, None, &at("23:30"))), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn start_up_puts_the_brightness_back() {
+        let schedules = vec![
+            schedule(1, "16:00", "set_brightness", Some("16:00")),
+            schedule(2, "18:00", "play_animation", Some("18:00")),
+        ];
+        assert_eq!(ids(due(&schedules, None, &at("23:30"))), vec![1]);
+    }
+
+    #[test]
+    fn only_transitions_since_the_last_check_fire() {
+        let schedules = vec![
+            schedule(1, "16:00", "set_brightness", None),
+            schedule(2, "18:00", "play_animation", None),
+        ];
+        assert_eq!(
+            ids(due(&schedules, Some(at("17:59")), &at("18:00"))),
+            vec![2]
+        );
+        assert_eq!(
+            ids(due(&schedules, Some(at("18:00")), &at("18:01"))),
+            Vec::<i32>::new()
+        );
+    }
+}
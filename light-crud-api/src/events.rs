@@ -0,0 +1,184 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Router,
+};
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::database::initialize::AppState;
+use crate::database::schedule::ScheduleAction;
+use crate::lights::brightness::{BrightnessTarget, Easing};
+use crate::lights::playback::PlaybackMode;
+
+/// Events each subscriber can fall behind by before it starts missing them
+const SUBSCRIBER_BACKLOG: usize = 64;
+
+/// Something that happened that a dashboard might want to know about
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    AnimationStarted {
+        id: i32,
+        name: String,
+        mode: PlaybackMode,
+    },
+    /// A play once or repeat animation got to the end
+    AnimationFinished {
+        id: i32,
+        name: String,
+        loops: u32,
+    },
+    BrightnessChanged {
+        target: BrightnessTarget,
+        value: u8,
+        duration_ms: u128,
+        curve: Easing,
+    },
+    ScheduleFired {
+        id: i32,
+        name: String,
+        action: ScheduleAction,
+        value: Option<i64>,
+    },
+    /// An outside trigger, e.g. a motion sensor
+    TriggerReceived {
+        name: String,
+        source: &'static str,
+        value: Option<Value>,
+    },
+    /// `entity` is the route the entity lives under, e.g. "playlist"
+    EntityCreated {
+        entity: &'static str,
+        id: i64,
+    },
+    EntityUpdated {
+        entity: &'static str,
+        id: i64,
+    },
+    EntityDeleted {
+        entity: &'static str,
+        id: i64,
+    },
+    ShutdownRequested,
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::AnimationStarted { .. } => "animation_started",
+            Event::AnimationFinished { .. } => "animation_finished",
+            Event::BrightnessChanged { .. } => "brightness_changed",
+            Event::ScheduleFired { .. } => "schedule_fired",
+            Event::TriggerReceived { .. } => "trigger_received",
+            Event::EntityCreated { .. } => "entity_created",
+            Event::EntityUpdated { .. } => "entity_updated",
+            Event::EntityDeleted { .. } => "entity_deleted",
+            Event::ShutdownRequested => "shutdown_requested",
+        }
+    }
+}
+
+/// Passes events from the light loop, the scheduler and the web handlers to whoever is
+/// listening. Publishing never waits, a subscriber that is too slow misses events instead.
+#[derive(Clone, Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus {
+            sender: broadcast::channel(SUBSCRIBER_BACKLOG).0,
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // only fails when nobody is listening
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        return self.sender.subscribe();
+    }
+}
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/", get(get_events))
+        .route("/trigger/:name", post(post_trigger))
+        .with_state(state);
+
+    index.insert("/events", "GET (Server-Sent Events)");
+    index.insert("/events/trigger/:name", "POST");
+    return app;
+}
+
+/// Streams events as Server-Sent Events, named by their type with the event as JSON data.
+///
+/// The stream ends after `shutdown_requested` so it does not hold the web server open.
+pub async fn get_events(State(state): State<Arc<AppState>>) -> Response {
+    let receiver = state.events.subscribe();
+    let stream = futures::stream::unfold(Some(receiver), |receiver| async move {
+        let mut receiver = receiver?;
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                let lagged = sse::Event::default()
+                    .event("lagged")
+                    .data(json!({"type": "lagged", "missed": missed}).to_string());
+                return Some((Ok::<_, Infallible>(lagged), Some(receiver)));
+            }
+            Err(RecvError::Closed) => return None,
+        };
+        let message = sse::Event::default()
+            .event(event.name())
+            .data(serde_json::to_string(&event).unwrap());
+        return match event {
+            Event::ShutdownRequested => Some((Ok(message), None)),
+            _ => Some((Ok(message), Some(receiver))),
+        };
+    });
+    return Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response();
+}
+
+/// Lets something outside, like a sensor, tell everyone listening that it went off. The body
+/// is passed along as the value if it is JSON.
+pub async fn post_trigger(
+    Path(name): Path<String>,
+    State(state): State<Arc<AppState>>,
+    payload: String,
+) -> Response {
+    let value = match payload.trim().is_empty() {
+        true => None,
+        false => match serde_json::from_str::<Value>(&payload) {
+            Ok(value) => Some(value),
+            Err(error) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    json!({"error": format!("the body has to be JSON or empty: {error}")})
+                        .to_string(),
+                )
+                    .into_response()
+            }
+        },
+    };
+    state.events.publish(Event::TriggerReceived {
+        name: name.clone(),
+        source: "http",
+        value: value.clone(),
+    });
+    return json!({"trigger": name, "value": value})
+        .to_string()
+        .into_response();
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::net::UdpSocket;
+
+use super::dmx::{is_late_sequence, receive_packet, FrameAssembler, UniverseMap, UNIVERSE_SLOTS};
+use super::live::{LiveFrame, LiveInput, LiveSource};
+
+use crate::config::{ArtNetConfig, LightsConfig};
+use crate::thread_utils::NotifyChecker;
+
+/// Every Art-Net packet starts with this
+const ART_NET_ID: &[u8; 8] = b"Art-Net\0";
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+/// Art-Net 4
+const PROTOCOL_VERSION: u16 = 14;
+/// Offset of the DMX data in an ArtDmx packet
+const DMX_OFFSET: usize = 18;
+const MAX_PACKET: usize = DMX_OFFSET + UNIVERSE_SLOTS;
+const POLL_REPLY_LENGTH: usize = 239;
+/// Ports described by each ArtPollReply
+const PORTS_PER_REPLY: usize = 4;
+/// Port can output DMX512 from the network
+const PORT_TYPE_DMX_OUTPUT: u8 = 0x80;
+
+/// Reads the parts of an ArtDmx packet the receiver uses, the universe, sequence and data
+fn parse_dmx(packet: &[u8]) -> Option<(u16, u8, &[u8])> {
+    if packet.len() < DMX_OFFSET || u16::from_be_bytes([packet[10], packet[11]]) < PROTOCOL_VERSION
+    {
+        return None;
+    }
+    // the port address, net in the high byte and sub-net and universe in the low byte
+    let universe = u16::from_le_bytes([packet[14], packet[15] & 0x7F]);
+    let length = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+    let data = packet.get(DMX_OFFSET..DMX_OFFSET + length.min(UNIVERSE_SLOTS))?;
+    return Some((universe, packet[12], data));
+}
+
+fn opcode(packet: &[u8]) -> Option<u16> {
+    if packet.len() < 10 || &packet[..8] != ART_NET_ID {
+        return None;
+    }
+    return Some(u16::from_le_bytes([packet[8], packet[9]]));
+}
+
+/// The address controllers should send to, the one this machine uses to reach `controller`
+fn local_address(controller: SocketAddr) -> Ipv4Addr {
+    let probe = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|probe| probe.connect(controller).map(|_| probe))
+        .and_then(|probe| probe.local_addr());
+    return match probe.map(|address| address.ip()) {
+        Ok(IpAddr::V4(address)) => address,
+        _ => Ipv4Addr::UNSPECIFIED,
+    };
+}
+
+fn copy_name(field: &mut [u8], name: &str) {
+    // always leave room for the null at the end
+    let length = name.len().min(field.len() - 1);
+    field[..length].copy_from_slice(&name.as_bytes()[..length]);
+}
+
+/// ArtPollReply packets describing the node, one for each four universes that share a net and
+/// sub-net
+fn poll_replies(
+    config: &ArtNetConfig,
+    map: &UniverseMap,
+    address: Ipv4Addr,
+    frames: u64,
+) -> Vec<Vec<u8>> {
+    let mut groups: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+    for universe in map.universes() {
+        groups.entry(universe >> 4).or_default().push(universe);
+    }
+    let ports: Vec<(u16, &[u16])> = groups
+        .iter()
+        .flat_map(|(group, universes)| {
+            universes
+                .chunks(PORTS_PER_REPLY)
+                .map(move |chunk| (*group, chunk))
+        })
+        .collect();
+
+    let mut replies = Vec::new();
+    for (index, (group, universes)) in ports.iter().enumerate() {
+        let mut reply = vec![0u8; POLL_REPLY_LENGTH];
+        reply[..8].copy_from_slice(ART_NET_ID);
+        reply[8..10].copy_from_slice(&OP_POLL_REPLY.to_le_bytes());
+        reply[10..14].copy_from_slice(&address.octets());
+        reply[14..16].copy_from_slice(&config.port.to_le_bytes());
+        // firmware version
+        reply[16..18].copy_from_slice(&1u16.to_be_bytes());
+        reply[18] = (group >> 4) as u8;
+        reply[19] = (group & 0x0F) as u8;
+        // OemUnknown
+        reply[20..22].copy_from_slice(&0x00FFu16.to_be_bytes());
+        // indicators normal, addresses set from the front panel, which here is the config
+        reply[23] = 0xD0;
+        copy_name(&mut reply[26..44], &config.name);
+        copy_name(&mut reply[44..108], &config.name);
+        copy_name(
+            &mut reply[108..172],
+            &format!("#0001 [{:04}] {frames} frames received", frames % 10000),
+        );
+        reply[172..174].copy_from_slice(&(universes.len() as u16).to_be_bytes());
+        for (port, universe) in universes.iter().enumerate() {
+            reply[174 + port] = PORT_TYPE_DMX_OUTPUT;
+            reply[190 + port] = (universe & 0x0F) as u8;
+        }
+        reply[207..211].copy_from_slice(&address.octets());
+        reply[211] = index as u8 + 1;
+        // supports 15 bit port addresses
+        reply[212] = 0x08;
+        replies.push(reply);
+    }
+    return replies;
+}
+
+/// Listens for Art-Net and hands every complete frame to the light loop as live frames, which
+/// show in place of the animation until the data stops for the timeout
+pub async fn run_receiver(
+    config: ArtNetConfig,
+    lights: LightsConfig,
+    live: tokio::sync::mpsc::Sender<LiveInput>,
+    shutdown: NotifyChecker,
+) {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.port)).await {
+        Ok(socket) => socket,
+        Err(error) => {
+            println!("Art-Net: could not listen on port {}: {error}", config.port);
+            return;
+        }
+    };
+    let mut assembler = FrameAssembler::new(UniverseMap::new(&lights, &config.outputs));
+    let universes: Vec<u16> = assembler.map().universes().collect();
+    println!(
+        "Art-Net: listening on port {} for universes {universes:?}",
+        config.port
+    );
+
+    let timeout = Duration::from_millis(config.timeout_ms.unwrap_or(lights.live_timeout_ms));
+    // last sequence number from each controller for each universe, zero turns the check off
+    let mut sequences: HashMap<(u16, IpAddr), u8> = HashMap::new();
+    let mut frames: u64 = 0;
+    let mut buffer = [0u8; MAX_PACKET];
+    while let Some((length, from)) =
+        receive_packet(&socket, &mut buffer, &shutdown, "Art-Net").await
+    {
+        let packet = &buffer[..length];
+        match opcode(packet) {
+            Some(OP_POLL) => {
+                for reply in poll_replies(&config, assembler.map(), local_address(from), frames) {
+                    if let Err(error) = socket.send_to(&reply, from).await {
+                        println!("Art-Net: could not answer a poll from {from}: {error}");
+                    }
+                }
+            }
+            Some(OP_DMX) => {
+                let (universe, sequence, data) = match parse_dmx(packet) {
+                    Some(dmx) if assembler.map().contains(dmx.0) => dmx,
+                    _ => continue,
+                };
+                let key = (universe, from.ip());
+                match sequences.get(&key) {
+                    Some(last) if sequence != 0 && is_late_sequence(sequence, *last) => continue,
+                    Some(_) => {}
+                    None => println!("Art-Net: {} is sending universe {universe}", from.ip()),
+                }
+                sequences.insert(key, sequence);
+                if let Some(pixels) = assembler.receive(universe, data) {
+                    frames += 1;
+                    let frame = LiveFrame {
+                        pixels: pixels,
+                        source: LiveSource::ArtNet,
+                        timeout: timeout,
+                    };
+                    if live.send(LiveInput::Frame(frame)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    println!("Art-Net: Stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dmx(universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; DMX_OFFSET];
+        packet[..8].copy_from_slice(ART_NET_ID);
+        packet[8..10].copy_from_slice(&OP_DMX.to_le_bytes());
+        packet[10..12].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        packet[12] = sequence;
+        packet[14..16].copy_from_slice(&universe.to_le_bytes());
+        packet[16..18].copy_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(data);
+        return packet;
+    }
+
+    fn config(led_count: usize, universe: u16) -> (ArtNetConfig, UniverseMap) {
+        let lights: LightsConfig = toml::from_str(&format!(
+            r#"
+            [[channels]]
+            name = "test"
+            pin = 12
+            led_count = {led_count}
+            strip_type = "ws2811_rgb"
+            brightness = 255
+            "#
+        ))
+        .unwrap();
+        let config: ArtNetConfig = toml::from_str(&format!(
+            r#"
+            name = "porch"
+            [[outputs]]
+            channel = "test"
+            universe = {universe}
+            "#
+        ))
+        .unwrap();
+        let map = UniverseMap::new(&lights, &config.outputs);
+        return (config, map);
+    }
+
+    #[test]
+    fn parses_a_dmx_packet() {
+        let packet = dmx(0x0123, 9, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(opcode(&packet), Some(OP_DMX));
+        assert_eq!(
+            parse_dmx(&packet),
+            Some((0x0123, 9, [1, 2, 3, 4, 5, 6].as_slice()))
+        );
+    }
+
+    #[test]
+    fn rejects_a_truncated_dmx_packet() {
+        let packet = dmx(1, 0, &[1, 2, 3, 4, 5, 6]);
+        // shorter than the length says
+        assert_eq!(parse_dmx(&packet[..packet.len() - 1]), None);
+        // cut off in the header
+        assert_eq!(parse_dmx(&packet[..DMX_OFFSET - 1]), None);
+        assert_eq!(opcode(&packet[..9]), None);
+    }
+
+    #[test]
+    fn rejects_other_protocols_and_old_versions() {
+        let mut packet = dmx(1, 0, &[1, 2, 3]);
+        packet[..8].copy_from_slice(b"Art-Ext\0");
+        assert_eq!(opcode(&packet), None);
+
+        let mut packet = dmx(1, 0, &[1, 2, 3]);
+        packet[10..12].copy_from_slice(&13u16.to_be_bytes());
+        assert_eq!(parse_dmx(&packet), None);
+    }
+
+    #[test]
+    fn replies_to_a_poll_with_four_ports_each() {
+        // 170 LEDs to a universe, so 1000 LEDs need six universes
+        let (config, map) = config(1000, 0x0120);
+        let address = Ipv4Addr::new(192, 168, 1, 20);
+        let replies = poll_replies(&config, &map, address, 12);
+        assert_eq!(replies.len(), 2);
+        for (index, reply) in replies.iter().enumerate() {
+            assert_eq!(reply.len(), POLL_REPLY_LENGTH);
+            assert_eq!(opcode(reply), Some(OP_POLL_REPLY));
+            assert_eq!(&reply[10..14], &address.octets());
+            assert_eq!(&reply[14..16], &config.port.to_le_bytes());
+            // net 1, sub-net 2
+            assert_eq!(&reply[18..20], &[1, 2]);
+            assert_eq!(&reply[26..32], b"porch\0");
+            assert_eq!(reply[211], index as u8 + 1);
+        }
+        assert_eq!(&replies[0][172..174], &4u16.to_be_bytes());
+        assert_eq!(&replies[0][190..194], &[0, 1, 2, 3]);
+        assert_eq!(&replies[0][174..178], &[PORT_TYPE_DMX_OUTPUT; 4]);
+        assert_eq!(&replies[1][172..174], &2u16.to_be_bytes());
+        assert_eq!(&replies[1][190..194], &[4, 5, 0, 0]);
+        assert_eq!(
+            &replies[1][174..178],
+            &[PORT_TYPE_DMX_OUTPUT, PORT_TYPE_DMX_OUTPUT, 0, 0]
+        );
+    }
+
+    #[test]
+    fn universes_in_different_sub_nets_get_their_own_reply() {
+        // 200 LEDs need two universes, which straddle sub-nets 0 and 1
+        let (config, map) = config(200, 0x000F);
+        let replies = poll_replies(&config, &map, Ipv4Addr::LOCALHOST, 0);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(&replies[0][18..20], &[0, 0]);
+        assert_eq!(replies[0][190], 0x0F);
+        assert_eq!(&replies[1][18..20], &[0, 1]);
+        assert_eq!(replies[1][190], 0);
+    }
+}
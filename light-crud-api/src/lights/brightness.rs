@@ -0,0 +1,579 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::output::LedOutput;
+
+use crate::config::LightsConfig;
+use crate::database::initialize::AppState;
+
+/// How a brightness ramp moves from the old value to the new one over its duration
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Easing {
+    /// The same change every second
+    #[default]
+    Linear,
+    /// Starts slowly and speeds up
+    EaseIn,
+    /// Starts quickly and slows down towards the new value
+    EaseOut,
+    /// Slow at both ends, quicker in the middle
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps how far through the ramp we are onto how far the brightness has moved
+    pub fn curve(&self, progress: f64) -> f64 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => progress,
+            Easing::EaseIn => progress * progress,
+            Easing::EaseOut => progress * (2.0 - progress),
+            Easing::EaseInOut => progress * progress * (3.0 - 2.0 * progress),
+        }
+    }
+}
+
+/// Which brightness level a request changes
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrightnessTarget {
+    /// Every channel's own level
+    AllChannels,
+    /// One channel's own level, by its index in `lights.channels`
+    Channel(usize),
+    /// The level every channel is scaled by
+    Master,
+}
+
+/// A brightness change sent to the light loop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BrightnessRequest {
+    pub target: BrightnessTarget,
+    pub value: u8,
+    /// How long to take getting there, zero jumps straight to it
+    pub duration: Duration,
+    pub easing: Easing,
+}
+
+/// Optional query parameters when setting a brightness, e.g. `?duration_ms=1800000&curve=ease_out`
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct BrightnessOptions {
+    /// How long to fade to the new brightness over, leave out to jump straight to it
+    pub duration_ms: Option<u64>,
+    pub curve: Option<Easing>,
+}
+
+impl BrightnessOptions {
+    pub fn into_request(self, target: BrightnessTarget, value: u8) -> BrightnessRequest {
+        return BrightnessRequest {
+            target: target,
+            value: value,
+            duration: Duration::from_millis(self.duration_ms.unwrap_or(0)),
+            easing: self.curve.unwrap_or_default(),
+        };
+    }
+}
+
+/// What the status reports about a ramp that is running
+#[derive(Clone, Debug, Serialize)]
+pub struct RampStatus {
+    pub target: u8,
+    pub easing: Easing,
+    pub duration_ms: u128,
+    pub remaining_ms: u128,
+}
+
+/// A brightness ramp that is currently playing out in the light loop
+#[derive(Clone, Debug)]
+pub struct BrightnessRamp {
+    from: u8,
+    to: u8,
+    easing: Easing,
+    started: Instant,
+    duration: Duration,
+}
+
+impl BrightnessRamp {
+    /// Returns None when the request should be applied straight away
+    pub fn start(from: u8, request: BrightnessRequest, now: Instant) -> Option<Self> {
+        if request.duration.is_zero() {
+            return None;
+        }
+        return Some(BrightnessRamp {
+            from: from,
+            to: request.value,
+            easing: request.easing,
+            started: now,
+            duration: request.duration,
+        });
+    }
+
+    pub fn is_finished(&self, now: Instant) -> bool {
+        return now.duration_since(self.started) >= self.duration;
+    }
+
+    /// Brightness the ramp is at `now`
+    pub fn level(&self, now: Instant) -> u8 {
+        let from = self.from as f64;
+        let progress = now.duration_since(self.started).as_secs_f64() / self.duration.as_secs_f64();
+        let amount = self.easing.curve(progress);
+        return (from + (self.to as f64 - from) * amount).round() as u8;
+    }
+
+    /// How long the loop can wait before the brightness needs updating again. The curves change
+    /// at most twice as fast as a linear ramp, so this is short enough to show every step, but
+    /// never quicker than `refresh_period`.
+    pub fn step_period(&self, refresh_period: Duration) -> Duration {
+        let steps = self.from.abs_diff(self.to) as u32;
+        if steps == 0 {
+            return self.duration;
+        }
+        return (self.duration / (steps * 2)).max(refresh_period);
+    }
+
+    pub fn status(&self, now: Instant) -> RampStatus {
+        return RampStatus {
+            target: self.to,
+            easing: self.easing,
+            duration_ms: self.duration.as_millis(),
+            remaining_ms: self
+                .duration
+                .saturating_sub(now.duration_since(self.started))
+                .as_millis(),
+        };
+    }
+}
+
+/// A brightness level along with the ramp moving it, if there is one
+#[derive(Clone, Debug)]
+struct Level {
+    value: u8,
+    ramp: Option<BrightnessRamp>,
+}
+
+impl Level {
+    fn new(value: u8) -> Self {
+        Level {
+            value: value,
+            ramp: None,
+        }
+    }
+
+    fn at(&self, now: Instant) -> u8 {
+        return self
+            .ramp
+            .as_ref()
+            .map_or(self.value, |ramp| ramp.level(now));
+    }
+
+    /// A newer request replaces a ramp that is still running, starting from wherever it had
+    /// got to
+    fn set(&mut self, request: BrightnessRequest, now: Instant) {
+        let from = self.at(now);
+        self.ramp = BrightnessRamp::start(from, request, now);
+        self.value = match self.ramp {
+            Some(_) => from,
+            None => request.value,
+        };
+    }
+
+    fn update(&mut self, now: Instant) {
+        if let Some(ramp) = &self.ramp {
+            self.value = ramp.level(now);
+            if ramp.is_finished(now) {
+                self.ramp = None;
+            }
+        }
+    }
+
+    fn ramp_status(&self, now: Instant) -> Option<RampStatus> {
+        return self.ramp.as_ref().map(|ramp| ramp.status(now));
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChannelBrightness {
+    pub channel: String,
+    /// What the channel is being driven at, its level scaled by the master level
+    pub brightness: u8,
+    /// The channel's own level
+    pub level: u8,
+    pub ramp: Option<RampStatus>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MasterBrightness {
+    pub level: u8,
+    pub ramp: Option<RampStatus>,
+}
+
+/// Brightness levels for each channel and the master level they are scaled by, owned by the
+/// light loop. A master level of 255 leaves the channel levels as they are.
+#[derive(Clone, Debug)]
+pub struct BrightnessMixer {
+    channels: Vec<Level>,
+    master: Level,
+}
+
+impl BrightnessMixer {
+    pub fn from_config(lights: &LightsConfig) -> Self {
+        BrightnessMixer {
+            channels: lights
+                .channels
+                .iter()
+                .map(|channel| Level::new(channel.brightness))
+                .collect(),
+            master: Level::new(255),
+        }
+    }
+
+    pub fn apply(&mut self, request: BrightnessRequest, now: Instant) {
+        match request.target {
+            BrightnessTarget::AllChannels => {
+                for level in self.channels.iter_mut() {
+                    level.set(request, now);
+                }
+            }
+            BrightnessTarget::Channel(channel) => match self.channels.get_mut(channel) {
+                Some(level) => level.set(request, now),
+                None => {
+                    println!("Controller: there is no channel {channel} to set the brightness of")
+                }
+            },
+            BrightnessTarget::Master => self.master.set(request, now),
+        }
+    }
+
+    /// Moves any running ramps on to `now` and sets the brightness of every channel on the output
+    pub fn write(&mut self, now: Instant, output: &mut dyn LedOutput) {
+        self.master.update(now);
+        for (channel, level) in self.channels.iter_mut().enumerate() {
+            level.update(now);
+            output.set_brightness(channel, scale(level.value, self.master.value));
+        }
+    }
+
+    /// How long until a ramp needs the output updating again, None when nothing is ramping
+    pub fn step_period(&self, refresh_period: Duration) -> Option<Duration> {
+        return self
+            .channels
+            .iter()
+            .chain(std::iter::once(&self.master))
+            .filter_map(|level| level.ramp.as_ref())
+            .map(|ramp| ramp.step_period(refresh_period))
+            .min();
+    }
+
+    pub fn channel_status(&self, lights: &LightsConfig, now: Instant) -> Vec<ChannelBrightness> {
+        let master = self.master.at(now);
+        return lights
+            .channels
+            .iter()
+            .zip(self.channels.iter())
+            .map(|(channel, level)| ChannelBrightness {
+                channel: channel.name.clone(),
+                brightness: scale(level.at(now), master),
+                level: level.at(now),
+                ramp: level.ramp_status(now),
+            })
+            .collect();
+    }
+
+    pub fn master_status(&self, now: Instant) -> MasterBrightness {
+        return MasterBrightness {
+            level: self.master.at(now),
+            ramp: self.master.ramp_status(now),
+        };
+    }
+}
+
+/// Scales a channel level by the master level
+fn scale(level: u8, master: u8) -> u8 {
+    return ((level as u32 * master as u32 + 127) / 255) as u8;
+}
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/", get(get_brightness))
+        .route("/master/:value", post(set_master))
+        .route("/channel/:channel/:value", post(set_channel))
+        .with_state(state);
+
+    index.insert("/brightness", "GET");
+    index.insert("/brightness/master/:value", "POST");
+    index.insert("/brightness/channel/:channel/:value", "POST");
+    return app;
+}
+
+/// Returns the master level and each channel's level, as last rendered by the light loop
+pub async fn get_brightness(State(state): State<Arc<AppState>>) -> Response {
+    let status = state.lights_status.lock().unwrap();
+    return json!({
+        "master": status.player.master_brightness,
+        "channels": status.player.brightness,
+    })
+    .to_string()
+    .into_response();
+}
+
+/// Sends a brightness change to the light loop, replacing any ramp that is running for the
+/// same level
+pub async fn send_brightness(state: &AppState, request: BrightnessRequest) -> Response {
+    state.send_to_brightness.send(request).await.unwrap();
+    return (
+        StatusCode::OK,
+        json!({
+            "target": request.target,
+            "brightness": request.value,
+            "duration_ms": request.duration.as_millis(),
+            "curve": request.easing,
+        })
+        .to_string(),
+    )
+        .into_response();
+}
+
+async fn set_master(
+    Path(value): Path<u8>,
+    Query(options): Query<BrightnessOptions>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let request = options.into_request(BrightnessTarget::Master, value);
+    return send_brightness(&state, request).await;
+}
+
+/// Sets one channel's level, `channel` is its name or index in the config
+async fn set_channel(
+    Path((channel, value)): Path<(String, u8)>,
+    Query(options): Query<BrightnessOptions>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let index = match find_channel(&state.lights, &channel) {
+        Some(index) => index,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                json!({"error": format!("there is no channel {channel:?}")}).to_string(),
+            )
+                .into_response()
+        }
+    };
+    let request = options.into_request(BrightnessTarget::Channel(index), value);
+    return send_brightness(&state, request).await;
+}
+
+/// Looks a channel up by name, or by its index in `lights.channels`
+pub fn find_channel(lights: &LightsConfig, channel: &str) -> Option<usize> {
+    return lights.channel_index(channel).or_else(|| {
+        channel
+            .parse::<usize>()
+            .ok()
+            .filter(|index| *index < lights.channels.len())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::simulated::SimulatedOutput;
+
+    fn ms(milliseconds: u64) -> Duration {
+        return Duration::from_millis(milliseconds);
+    }
+
+    fn request(target: BrightnessTarget, value: u8, duration: Duration) -> BrightnessRequest {
+        return BrightnessRequest {
+            target: target,
+            value: value,
+            duration: duration,
+            easing: Easing::Linear,
+        };
+    }
+
+    #[test]
+    fn every_curve_runs_from_start_to_end() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.curve(0.0), 0.0, "{easing:?}");
+            assert_eq!(easing.curve(1.0), 1.0, "{easing:?}");
+            assert_eq!(easing.curve(-0.5), 0.0, "{easing:?}");
+            assert_eq!(easing.curve(1.5), 1.0, "{easing:?}");
+        }
+    }
+
+    #[test]
+    fn curves_differ_half_way() {
+        assert_eq!(Easing::Linear.curve(0.5), 0.5);
+        assert_eq!(Easing::EaseIn.curve(0.5), 0.25);
+        assert_eq!(Easing::EaseOut.curve(0.5), 0.75);
+        assert_eq!(Easing::EaseInOut.curve(0.5), 0.5);
+        assert_eq!(Easing::EaseInOut.curve(0.25), 0.15625);
+    }
+
+    #[test]
+    fn a_ramp_moves_along_its_curve() {
+        let now = Instant::now();
+        let ramp =
+            BrightnessRamp::start(100, request(BrightnessTarget::Master, 200, ms(1000)), now)
+                .unwrap();
+        assert_eq!(ramp.level(now), 100);
+        assert_eq!(ramp.level(now + ms(250)), 125);
+        assert_eq!(ramp.level(now + ms(1000)), 200);
+        assert_eq!(ramp.level(now + ms(5000)), 200);
+        assert!(!ramp.is_finished(now + ms(999)));
+        assert!(ramp.is_finished(now + ms(1000)));
+
+        let mut eased = request(BrightnessTarget::Master, 200, ms(1000));
+        eased.easing = Easing::EaseOut;
+        let ramp = BrightnessRamp::start(100, eased, now).unwrap();
+        assert_eq!(ramp.level(now + ms(500)), 175);
+    }
+
+    #[test]
+    fn no_duration_jumps_straight_there() {
+        let now = Instant::now();
+        let jump = request(BrightnessTarget::Master, 40, Duration::ZERO);
+        assert!(BrightnessRamp::start(200, jump, now).is_none());
+        let mut level = Level::new(200);
+        level.set(jump, now);
+        assert_eq!(level.at(now), 40);
+        assert!(level.ramp.is_none());
+    }
+
+    #[test]
+    fn a_replaced_ramp_carries_on_from_where_it_had_got_to() {
+        let now = Instant::now();
+        let mut level = Level::new(0);
+        level.set(request(BrightnessTarget::Master, 200, ms(1000)), now);
+        assert_eq!(level.at(now + ms(500)), 100);
+
+        // half way up, turn round and go back down over another second
+        let later = now + ms(500);
+        level.set(request(BrightnessTarget::Master, 0, ms(1000)), later);
+        assert_eq!(level.at(later), 100);
+        assert_eq!(level.at(later + ms(500)), 50);
+
+        level.update(later + ms(1000));
+        assert_eq!(level.value, 0);
+        assert!(level.ramp.is_none());
+    }
+
+    #[test]
+    fn the_step_period_is_never_quicker_than_the_refresh_period() {
+        let now = Instant::now();
+        let refresh = ms(33);
+        let ramp = |from: u8, to: u8, duration: Duration| {
+            return BrightnessRamp::start(
+                from,
+                request(BrightnessTarget::Master, to, duration),
+                now,
+            )
+            .unwrap();
+        };
+        // 510 half steps in a second would be every 2ms
+        assert_eq!(ramp(0, 255, ms(1000)).step_period(refresh), refresh);
+        // 20 half steps in 10 seconds is every half a second
+        assert_eq!(ramp(0, 10, ms(10_000)).step_period(refresh), ms(500));
+        // nothing changes until the ramp ends
+        assert_eq!(ramp(80, 80, ms(2000)).step_period(refresh), ms(2000));
+    }
+
+    const TWO_CHANNELS: &str = r#"
+        [[channels]]
+        name = "entryway"
+        pin = 12
+        led_count = 2
+        strip_type = "ws2811_rgb"
+        brightness = 255
+
+        [[channels]]
+        name = "front_of_house"
+        pin = 19
+        led_count = 2
+        strip_type = "ws2811_rgb"
+        brightness = 100
+    "#;
+
+    #[test]
+    fn the_master_level_scales_each_channel() {
+        assert_eq!(scale(255, 128), 128);
+        assert_eq!(scale(200, 0), 0);
+        assert_eq!(scale(0, 255), 0);
+        assert_eq!(scale(200, 255), 200);
+        assert_eq!(scale(100, 128), 50);
+    }
+
+    #[test]
+    fn a_channel_request_changes_only_that_channel() {
+        let lights: LightsConfig = toml::from_str(TWO_CHANNELS).unwrap();
+        let mut mixer = BrightnessMixer::from_config(&lights);
+        let now = Instant::now();
+        mixer.apply(
+            request(BrightnessTarget::Channel(1), 50, Duration::ZERO),
+            now,
+        );
+        let levels: Vec<u8> = mixer
+            .channel_status(&lights, now)
+            .iter()
+            .map(|channel| channel.level)
+            .collect();
+        assert_eq!(levels, vec![255, 50]);
+
+        mixer.apply(
+            request(BrightnessTarget::AllChannels, 10, Duration::ZERO),
+            now,
+        );
+        let levels: Vec<u8> = mixer
+            .channel_status(&lights, now)
+            .iter()
+            .map(|channel| channel.level)
+            .collect();
+        assert_eq!(levels, vec![10, 10]);
+    }
+
+    #[test]
+    fn channels_are_driven_at_their_level_times_the_master() {
+        let lights: LightsConfig = toml::from_str(TWO_CHANNELS).unwrap();
+        let mut mixer = BrightnessMixer::from_config(&lights);
+        let mut output = SimulatedOutput::new(&lights.channel_lengths(), 255);
+        let now = Instant::now();
+        mixer.apply(request(BrightnessTarget::Master, 128, Duration::ZERO), now);
+        mixer.write(now, &mut output);
+        assert_eq!(output.brightness(0), 128);
+        assert_eq!(output.brightness(1), 50);
+
+        let status = mixer.channel_status(&lights, now);
+        assert_eq!(
+            (
+                status[1].channel.as_str(),
+                status[1].level,
+                status[1].brightness
+            ),
+            ("front_of_house", 100, 50)
+        );
+        assert_eq!(mixer.master_status(now).level, 128);
+
+        mixer.apply(request(BrightnessTarget::Master, 0, Duration::ZERO), now);
+        mixer.write(now, &mut output);
+        assert_eq!((output.brightness(0), output.brightness(1)), (0, 0));
+    }
+}
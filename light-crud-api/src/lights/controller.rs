@@ -1,67 +1,68 @@
-// use std::time::Duration;
+// use std::time::{Duration, Instant};
 
-use std::io;
-use std::io::Write;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use colored::Colorize;
-use futures::executor::block_on;
-// use futures::executor::block_on;
-use rs_ws281x::ChannelBuilder;
-use rs_ws281x::ControllerBuilder;
-use tokio::time::timeout;
-// use rs_ws281x::StripType;
-// use futures;
 
+use super::brightness::{BrightnessMixer, BrightnessRequest};
 use super::converter;
+use super::converter::ColorCorrection;
+use super::live::{LiveInput, LiveOverride};
+use super::mapping::PixelMap;
+use super::output::LedOutput;
+use super::playback::{PlayRequest, Playback, PlaybackMode};
+use super::player::PlayerCommand;
+use super::power::{PowerLimiter, PowerReport};
+use super::simulated::SimulatedOutput;
+use super::status::SharedStatus;
+use super::stream::FrameStream;
+use super::terminal::TerminalOutput;
+use super::timing::{FrameTimer, Ticker};
+use super::transition::ActiveTransition;
 
-use crate::config::Config;
+use crate::config::{DebugConfig, LightsConfig, OutputType};
 use crate::database::animation::Animation;
-use crate::database::frame::DataFrame;
+use crate::events::{Event, EventBus};
 use crate::thread_utils::NotifyChecker;
 
-const FRONT_OF_HOUSE_CHANNEL: usize = 1;
-const FRONT_OF_HOUSE_PIN: i32 = 19;
-
-const FRONT_ENTRYWAY_CHANNEL: usize = 0;
-const FRONT_ENTRYWAY_PIN: i32 = 12;
-
-pub fn setup() -> rs_ws281x::Controller {
-    // Construct a single channel controller. Note that the
-    // Controller is initialized by default and is cleaned up on drop
-
-    let mut controller: rs_ws281x::Controller = ControllerBuilder::new()
-        .freq(800_000)
-        .dma(10)
-        .channel(
-            FRONT_ENTRYWAY_CHANNEL, // Channel Index
-            ChannelBuilder::new()
-                .pin(FRONT_ENTRYWAY_PIN) // GPIO 12 = PWM0 // Default was 10
-                .count(250) // Number of LEDs
-                .strip_type(rs_ws281x::StripType::Ws2811Bgr)
-                .brightness(100) // default: 255
-                .build(),
-        )
-        .channel(
-            FRONT_OF_HOUSE_CHANNEL, // Channel Index
-            ChannelBuilder::new()
-                .pin(FRONT_OF_HOUSE_PIN) // GPIO 12 = PWM0 // Default was 10
-                .count(250) // Number of LEDs
-                .strip_type(rs_ws281x::StripType::Ws2811Bgr)
-                .brightness(100) // default: 255
-                .build(),
-        )
-        .build()
-        .unwrap();
-
-    let leds = controller.leds_mut(0);
-
-    // thinking the format is Red, Green, Blue
-    for led in leds.into_iter() {
-        *led = [0, 255, 0, 0];
+/// Longest the light loop sleeps before checking for new requests
+const MAX_IDLE: Duration = Duration::from_millis(50);
+
+#[cfg(feature = "rpi")]
+fn ws281x_output(lights: &LightsConfig) -> Box<dyn LedOutput> {
+    return Box::new(super::ws281x::Ws281xOutput::new(lights).unwrap());
+}
+
+#[cfg(not(feature = "rpi"))]
+fn ws281x_output(lights: &LightsConfig) -> Box<dyn LedOutput> {
+    println!(
+        "{}",
+        "Controller: built without the `rpi` feature, using the simulated output".red()
+    );
+    return Box::new(SimulatedOutput::new(&lights.channel_lengths(), 255));
+}
+
+/// Creates the output the light loop renders to, based off of the config
+pub fn setup(lights: &LightsConfig, debug: &DebugConfig) -> Box<dyn LedOutput> {
+    let mut output: Box<dyn LedOutput> = match lights.output_type(debug) {
+        OutputType::Ws281x => ws281x_output(lights),
+        OutputType::Terminal => {
+            println!("Controller: previewing the lights in the terminal");
+            Box::new(TerminalOutput::new(
+                &lights.channel_lengths(),
+                255,
+                lights.terminal_width,
+            ))
+        }
+        OutputType::Simulated => {
+            println!("Controller: using the simulated output");
+            Box::new(SimulatedOutput::new(&lights.channel_lengths(), 255))
+        }
+    };
+    for (index, channel) in lights.channels.iter().enumerate() {
+        output.set_brightness(index, channel.brightness);
     }
-    controller.render().unwrap();
-    return controller;
+    return output;
 }
 
 struct LedColor([u8; 4]);
@@ -78,79 +79,436 @@ impl LedColor {
 }
 
 #[allow(unused_mut)]
-pub fn write_frame(frame: &DataFrame, controller: &mut rs_ws281x::Controller) {
+pub fn write_frame(
+    frame: &[u32],
+    map: &PixelMap,
+    corrections: &[ColorCorrection],
+    power_limiter: &PowerLimiter,
+    output: &mut dyn LedOutput,
+    stream: &FrameStream,
+) -> PowerReport {
     // println!("write_frame: top");
-    for (led_color, mut led) in frame.data.iter().zip(controller.leds_mut(FRONT_OF_HOUSE_CHANNEL).iter_mut()) {
-        let bytes = converter::ByteRGB::from_u32(*led_color);
-        *led = [bytes.red, bytes.green, bytes.blue, 0];
-    }
-    for (led_color, mut led) in frame.data.iter().zip(controller.leds_mut(FRONT_ENTRYWAY_CHANNEL).iter_mut()) {
-        let bytes = converter::ByteRGB::from_u32(*led_color);
-        *led = [bytes.red, bytes.green, bytes.blue, 0];
+    for segment in map.segments.iter() {
+        let correction = &corrections[segment.channel];
+        let leds = output.leds_mut(segment.channel);
+        let pixels = frame.iter().skip(segment.start).take(segment.length);
+        for (index, led_color) in pixels.enumerate() {
+            let bytes = correction.apply(&converter::ByteRGB::from_u32(*led_color));
+            leds[segment.physical_index(index)] = [bytes.red, bytes.green, bytes.blue, 0];
+        }
     }
-    controller.render().unwrap();
+    let power_report = power_limiter.limit(output);
+    output.render().unwrap();
+    stream.publish(output);
     // println!("write_frame: bottom");
+    return power_report;
+}
+
+fn started_event(playback: &Playback) -> Event {
+    return Event::AnimationStarted {
+        id: playback.animation.id,
+        name: playback.animation.name.clone(),
+        mode: playback.mode,
+    };
 }
 
-pub async fn light_loop(
-    shutdown_notifier: NotifyChecker,
-    mut animation_receiver: tokio::sync::mpsc::Receiver<Animation>,
-    mut brightness_receiver: tokio::sync::mpsc::Receiver<u8>,
-) -> () {
+/// Everything the light loop renders to and takes requests from
+pub struct LightLoopIo {
+    pub output: Box<dyn LedOutput>,
+    pub status: SharedStatus,
+    pub frame_stream: FrameStream,
+    pub events: EventBus,
+    pub shutdown_notifier: NotifyChecker,
+    pub animation_receiver: tokio::sync::mpsc::Receiver<PlayRequest>,
+    pub brightness_receiver: tokio::sync::mpsc::Receiver<BrightnessRequest>,
+    pub player_receiver: tokio::sync::mpsc::Receiver<PlayerCommand>,
+    pub live_receiver: tokio::sync::mpsc::Receiver<LiveInput>,
+}
+
+pub async fn light_loop(lights: LightsConfig, io: LightLoopIo) -> () {
+    let LightLoopIo {
+        mut output,
+        status,
+        frame_stream,
+        events,
+        shutdown_notifier,
+        mut animation_receiver,
+        mut brightness_receiver,
+        mut player_receiver,
+        mut live_receiver,
+    } = io;
     println!("Controller: Starting");
     // let shutdown_notify_controller_loop = notifier.clone();
     // let mut animation_receiver = config.animation_comms.receving_channel;
     // let mut brightness_receiver = config.brightness_comms.receving_channel;
 
-    let mut controller = setup();
     // let looping_flag = shutdown_notifier.flag.clone();
 
-    let mut working_animation = Animation::new_with_single_frame(255);
-    working_animation.speed = 1.5;
-    let mut working_index = 0;
-    let mut working_frame_size = 1;
-    let mut working_time = (1000.0 / working_animation.speed) as u64;
+    let pixel_map = PixelMap::from_config(&lights);
+    let corrections: Vec<ColorCorrection> = lights
+        .channels
+        .iter()
+        .map(ColorCorrection::from_channel)
+        .collect();
+    let power_limiter = PowerLimiter::from_config(&lights);
+    let refresh_period = Duration::from_secs_f64(1.0 / lights.refresh_rate);
+
+    let mut default_animation = Animation::new_with_single_frame(255, pixel_map.frame_size);
+    default_animation.speed = 1.5;
+    let mut playback = Playback::new(PlayRequest::new(default_animation, &lights), Instant::now());
+    // what to go back to when a play once and revert animation finishes
+    let mut previous: Option<Playback> = None;
+    let mut transition: Option<ActiveTransition> = None;
+    let mut brightness = BrightnessMixer::from_config(&lights);
+    // showing black after a stop command, until it is resumed or something new is played
+    let mut stopped = false;
+    // frames pushed in from outside, shown instead of the animation until they stop coming
+    let mut live: Option<LiveOverride> = None;
+    let mut last_rendered: Vec<u32> = Vec::new();
+    let mut frame_timer = FrameTimer::new();
+    let mut ticker = Ticker::new(refresh_period, Instant::now());
+    let mut was_blending = false;
+    let mut render_at = Some(Instant::now());
+    {
+        let mut status = status.lock().unwrap();
+        status.player.loop_started = Some(Instant::now());
+        status.player.animation_started = Some(Instant::now());
+    }
     while !shutdown_notifier.is_notified() {
         // println!("top: {}", shutdown_notifier.is_notified());
         // if there is a new animation, load it and set the relevant counters
-        match timeout(Duration::from_micros(1), animation_receiver.recv()).await {
-            Err(err) => {
-                // println!("animation: {err}");
+        match animation_receiver.try_recv() {
+            Err(_) => {
+                // nothing new to play
+            }
+            Ok(request) if request.animation.frames.is_empty() => {
+                println!(
+                    "Controller: animation {} has no frames, ignoring it",
+                    request.animation.id
+                );
             }
-            Ok(value) => match value {
-                None => println!("Error on the animation receive"),
-                Some(frame) => {
-                    working_animation = frame;
-                    working_index = 0;
-                    working_frame_size = working_animation.frames.len();
-                    working_time = (1000.0 / working_animation.speed) as u64;
-                    println!("setting the loop time to {working_time:?}ms for {} fps", working_animation.speed);
+            Ok(request) => {
+                let now = Instant::now();
+                transition =
+                    ActiveTransition::start(last_rendered.clone(), request.transition, now);
+                let next = Playback::new(request, now);
+                // a play once and revert animation interrupted by another one still goes back
+                // to what was playing underneath it
+                let outgoing = std::mem::replace(&mut playback, next);
+                if outgoing.mode != PlaybackMode::OnceRevert {
+                    previous = Some(outgoing);
+                }
+                stopped = false;
+                live = None;
+                frame_timer.clear_window();
+                events.publish(started_event(&playback));
+                {
+                    let mut status = status.lock().unwrap();
+                    status.player.animation_started = Some(now);
+                    status.player.finished = false;
+                }
+                render_at = Some(now);
+                println!(
+                    "setting the loop time to {:?} for {} fps, {:?}",
+                    playback.frame_period(),
+                    playback.animation.speed,
+                    playback.mode
+                );
+            }
+        }
+        if let Ok(request) = brightness_receiver.try_recv() {
+            brightness.apply(request, Instant::now());
+            events.publish(Event::BrightnessChanged {
+                target: request.target,
+                value: request.value,
+                duration_ms: request.duration.as_millis(),
+                curve: request.easing,
+            });
+            println!(
+                "Setting the {:?} Brightness to {} over {:?}",
+                request.target, request.value, request.duration
+            );
+            render_at = Some(Instant::now());
+        }
+        if let Ok(command) = player_receiver.try_recv() {
+            let now = Instant::now();
+            match command {
+                PlayerCommand::Pause => playback.pause(now),
+                PlayerCommand::Resume => {
+                    if stopped {
+                        transition =
+                            ActiveTransition::start(last_rendered.clone(), lights.transition, now);
+                        stopped = false;
+                    }
+                    playback.unpause(now);
                 }
-            },
+                PlayerCommand::StepForward => playback.step_forward(now),
+                PlayerCommand::StepBack => playback.step_back(now),
+                PlayerCommand::SeekFrame(index) => playback.seek_frame(index, now),
+                PlayerCommand::SeekTime(offset) => playback.seek_time(offset, now),
+                PlayerCommand::Stop => {
+                    transition =
+                        ActiveTransition::start(last_rendered.clone(), lights.transition, now);
+                    playback.pause(now);
+                    playback.seek_frame(0, now);
+                    stopped = true;
+                }
+            }
+            println!("Controller: {:?}", command);
+            frame_timer.clear_window();
+            render_at = Some(now);
+        }
+        // only the newest live frame matters, so catch up on any that queued up
+        let mut stop_live = false;
+        while let Ok(input) = live_receiver.try_recv() {
+            let now = Instant::now();
+            match (input, &mut live) {
+                (LiveInput::Frame(frame), Some(showing)) => showing.update(frame, now),
+                (LiveInput::Frame(frame), None) => {
+                    println!("Controller: showing live frames from {:?}", frame.source);
+                    transition = None;
+                    live = Some(LiveOverride::new(frame, now));
+                }
+                (LiveInput::Stop, _) => stop_live = true,
+            }
+            render_at = Some(now);
+        }
+        let live_timed_out = live
+            .as_ref()
+            .is_some_and(|showing| Instant::now() >= showing.expires_at());
+        if stop_live || live_timed_out {
+            if let Some(ended) = live.take() {
+                let now = Instant::now();
+                println!(
+                    "Controller: live frames from {:?} stopped, going back to animation {}",
+                    ended.source(),
+                    playback.animation.id
+                );
+                transition = ActiveTransition::start(last_rendered.clone(), lights.transition, now);
+                // carry on from the frame it was on when the live frames took over
+                playback.resume(now);
+                frame_timer.clear_window();
+                render_at = Some(now);
+            }
         }
-        match timeout(Duration::from_micros(1), brightness_receiver.recv()).await {
-            Err(err) => {
-                // println!("brightness: {err}");
+
+        if render_at.is_some_and(|render_at| Instant::now() >= render_at) {
+            let render_started = Instant::now();
+            let was_finished = playback.is_finished();
+            // the animation waits where it is while live frames are showing
+            let frames_passed = match live {
+                Some(_) => 0,
+                None => playback.advance(render_started),
+            };
+            if playback.is_finished() && !was_finished {
+                events.publish(Event::AnimationFinished {
+                    id: playback.animation.id,
+                    name: playback.animation.name.clone(),
+                    loops: playback.loops,
+                });
+            }
+            if playback.is_finished() && playback.mode == PlaybackMode::OnceRevert {
+                if let Some(mut reverting) = previous.take() {
+                    println!(
+                        "Controller: going back to animation {}",
+                        reverting.animation.id
+                    );
+                    transition = ActiveTransition::start(
+                        last_rendered.clone(),
+                        lights.transition,
+                        render_started,
+                    );
+                    reverting.resume(render_started);
+                    playback = reverting;
+                    frame_timer.clear_window();
+                    events.publish(started_event(&playback));
+                    status.lock().unwrap().player.animation_started = Some(render_started);
+                }
             }
-            Ok(value) => match value {
-                None => println!("Error on the animation receive"),
-                Some(brightness_value) => {
-                    controller.set_brightness(0, brightness_value);
-                    controller.set_brightness(1, brightness_value);
-                    println!("Setting the Brightness to {}", brightness_value);
+            if transition
+                .as_ref()
+                .is_some_and(|active| active.is_finished(render_started))
+            {
+                transition = None;
+            }
+            let blending = live.is_none() && (transition.is_some() || playback.is_interpolating());
+            if blending && was_blending {
+                frame_timer.skipped(ticker.advance(render_started));
+            } else if blending {
+                ticker.reset(render_started);
+            } else {
+                frame_timer.skipped(frames_passed.saturating_sub(1));
+            }
+            was_blending = blending;
+
+            let mut working_frame = match (&live, stopped) {
+                (Some(showing), _) => {
+                    let mut frame = showing.frame.clone();
+                    frame.resize(pixel_map.frame_size, 0);
+                    frame
                 }
-            },
+                (None, true) => vec![0; pixel_map.frame_size],
+                (None, false) => playback.frame_at(render_started),
+            };
+            if let (None, Some(active)) = (&live, &transition) {
+                working_frame = active.apply(&working_frame, render_started);
+            }
+            brightness.write(render_started, output.as_mut());
+            let power_report = write_frame(
+                &working_frame,
+                &pixel_map,
+                &corrections,
+                &power_limiter,
+                output.as_mut(),
+                &frame_stream,
+            );
+            last_rendered = working_frame;
+            frame_timer.record(render_started, Instant::now());
+
+            let target_fps = match blending {
+                true => lights.refresh_rate,
+                false => playback.animation.speed,
+            };
+            {
+                let mut status = status.lock().unwrap();
+                status.power = power_report;
+                status.timing = frame_timer.report(target_fps);
+                status.player.update(
+                    &playback,
+                    stopped,
+                    transition.is_some(),
+                    &lights,
+                    &brightness,
+                    render_started,
+                );
+                status.player.live = live.as_ref().map(|showing| showing.status(render_started));
+            }
+
+            // while blending, frames are rendered at the refresh rate, otherwise there is nothing
+            // new to show until the animation moves on to its next frame. Both are absolute times
+            // so the time spent rendering does not push the schedule back. A finished animation
+            // holds its last frame and does not need rendering again. Live frames are rendered as
+            // they come in, and once more when they time out.
+            render_at = match &live {
+                Some(showing) => Some(showing.expires_at()),
+                None => playback.next_frame_at(),
+            };
+            if blending {
+                render_at =
+                    Some(render_at.map_or(ticker.next_tick(), |at| at.min(ticker.next_tick())));
+            }
+            // a brightness ramp only needs rendering as often as the brightness changes
+            if let Some(step_period) = brightness.step_period(refresh_period) {
+                let step_at = render_started + step_period;
+                render_at = Some(render_at.map_or(step_at, |at| at.min(step_at)));
+            }
         }
 
-        let working_frame = &working_animation.frames[working_index];
-        working_index += 1;
-        working_index = working_index % working_frame_size;
-        write_frame(working_frame, &mut controller);
-        std::thread::sleep(Duration::from_millis(working_time));
-        // tokio::time::sleep(Duration::from_millis(working_time)).await;
+        // wake up regularly even with nothing to render, so new requests and shutdown are seen
+        let idle_until = Instant::now() + MAX_IDLE;
+        let wake_at = render_at.map_or(idle_until, |render_at| render_at.min(idle_until));
+        tokio::time::sleep_until(tokio::time::Instant::from_std(wake_at)).await;
         // println!("bottom: {}", shutdown_notifier.is_notified());
     }
     println!("{}", "Controller: Stopping".red());
 }
 
 // /home/pi/Lights/db/sqlite.db
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lights(toml: &str) -> LightsConfig {
+        return toml::from_str(toml).unwrap();
+    }
+
+    const FOUR_LEDS: &str = r#"
+        [[channels]]
+        name = "test"
+        pin = 12
+        led_count = 4
+        strip_type = "ws2811_rgb"
+        brightness = 255
+    "#;
+
+    #[test]
+    fn write_frame_shows_the_frame_on_the_output() {
+        let lights = lights(FOUR_LEDS);
+        let mut output = SimulatedOutput::new(&lights.channel_lengths(), 255);
+        let display = output.display();
+        let corrections = vec![ColorCorrection::from_channel(&lights.channels[0])];
+        write_frame(
+            &[0xFF0000, 0x00FF00, 0x0000FF, 0x102030],
+            &PixelMap::from_config(&lights),
+            &corrections,
+            &PowerLimiter::from_config(&lights),
+            &mut output,
+            &FrameStream::new(),
+        );
+
+        let shown = display.last_frame().unwrap();
+        assert_eq!(
+            shown.channels,
+            vec![vec![
+                [255, 0, 0, 0],
+                [0, 255, 0, 0],
+                [0, 0, 255, 0],
+                [0x10, 0x20, 0x30, 0]
+            ]]
+        );
+        assert_eq!(shown.brightness, vec![255]);
+        assert_eq!(display.render_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn light_loop_plays_a_requested_animation() {
+        let lights = lights(&format!(
+            "{FOUR_LEDS}\n[transition]\nkind = \"cut\"\nduration_ms = 0"
+        ));
+        let output = SimulatedOutput::new(&lights.channel_lengths(), 255);
+        let display = output.display();
+        let status = SharedStatus::default();
+        let shutdown = NotifyChecker::new();
+        let (animations, animation_receiver) = tokio::sync::mpsc::channel(1);
+        let (_brightness, brightness_receiver) = tokio::sync::mpsc::channel(1);
+        let (_player, player_receiver) = tokio::sync::mpsc::channel(1);
+        let (_live, live_receiver) = tokio::sync::mpsc::channel(1);
+        // the output is not Send, so the loop runs on this task alongside the test
+        let io = LightLoopIo {
+            output: Box::new(output),
+            status: status.clone(),
+            frame_stream: FrameStream::new(),
+            events: EventBus::new(),
+            shutdown_notifier: shutdown.clone(),
+            animation_receiver: animation_receiver,
+            brightness_receiver: brightness_receiver,
+            player_receiver: player_receiver,
+            live_receiver: live_receiver,
+        };
+        let running = light_loop(lights.clone(), io);
+
+        let check = async {
+            let mut animation = Animation::new_with_single_frame(0x00FF00, 4);
+            animation.id = 7;
+            animations
+                .send(PlayRequest::new(animation, &lights))
+                .await
+                .unwrap();
+            let green = vec![vec![[0, 255, 0, 0]; 4]];
+            let mut shown = None;
+            for _ in 0..40 {
+                tokio::time::sleep(Duration::from_millis(25)).await;
+                shown = display.last_frame().map(|frame| frame.channels);
+                if shown.as_ref() == Some(&green) {
+                    break;
+                }
+            }
+            assert_eq!(shown, Some(green));
+            assert_eq!(status.lock().unwrap().player.animation_id, 7);
+
+            shutdown.set_notified();
+        };
+        tokio::join!(running, check);
+    }
+}
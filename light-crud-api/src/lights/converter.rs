@@ -1,3 +1,5 @@
+use crate::config::ChannelConfig;
+
 #[derive(Debug)]
 pub struct ByteRGB {
     pub red: u8,
@@ -14,3 +16,121 @@ impl ByteRGB {
         }
     }
 }
+
+/// Packs red, green and blue into the `0xRRGGBB` colours frames are stored as
+pub fn rgb_to_u32(red: u8, green: u8, blue: u8) -> u32 {
+    return ((red as u32) << 16) | ((green as u32) << 8) | blue as u32;
+}
+
+/// Colour correction for one channel, applied between the stored frame data and the LEDs.
+///
+/// The gamma curve, white balance and colour temperature are folded into one lookup table per
+/// colour so correcting a pixel is three array reads.
+#[derive(Clone)]
+pub struct ColorCorrection {
+    lookup: [[u8; 256]; 3],
+}
+
+impl std::fmt::Debug for ColorCorrection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.debug_struct("ColorCorrection").finish_non_exhaustive();
+    }
+}
+
+impl ColorCorrection {
+    pub fn new(gamma: f64, white_balance: [f64; 3], color_temperature: Option<u32>) -> Self {
+        let tint = match color_temperature {
+            Some(kelvin) => temperature_to_rgb(kelvin),
+            None => [1.0, 1.0, 1.0],
+        };
+        let mut lookup = [[0u8; 256]; 3];
+        for (color, table) in lookup.iter_mut().enumerate() {
+            let scale = white_balance[color] * tint[color];
+            for (value, entry) in table.iter_mut().enumerate() {
+                let corrected = (value as f64 / 255.0).powf(gamma) * scale * 255.0;
+                *entry = corrected.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        return ColorCorrection { lookup };
+    }
+
+    pub fn from_channel(channel: &ChannelConfig) -> Self {
+        return ColorCorrection::new(
+            channel.gamma,
+            channel.white_balance,
+            channel.color_temperature,
+        );
+    }
+
+    pub fn apply(&self, color: &ByteRGB) -> ByteRGB {
+        return ByteRGB {
+            red: self.lookup[0][color.red as usize],
+            green: self.lookup[1][color.green as usize],
+            blue: self.lookup[2][color.blue as usize],
+        };
+    }
+}
+
+/// Approximate RGB scale factors of a black body at the given colour temperature.
+///
+/// Uses Tanner Helland's fit of the CIE data, which comes out close to white around 6500K.
+pub fn temperature_to_rgb(kelvin: u32) -> [f64; 3] {
+    let temperature = kelvin as f64 / 100.0;
+    let red = if temperature <= 66.0 {
+        255.0
+    } else {
+        329.698727446 * (temperature - 60.0).powf(-0.1332047592)
+    };
+    let green = if temperature <= 66.0 {
+        99.4708025861 * temperature.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (temperature - 60.0).powf(-0.0755148492)
+    };
+    let blue = if temperature >= 66.0 {
+        255.0
+    } else if temperature <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (temperature - 10.0).ln() - 305.0447927307
+    };
+    return [red, green, blue].map(|value| value.clamp(0.0, 255.0) / 255.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correct(correction: &ColorCorrection, color: u32) -> [u8; 3] {
+        let corrected = correction.apply(&ByteRGB::from_u32(color));
+        return [corrected.red, corrected.green, corrected.blue];
+    }
+
+    #[test]
+    fn gamma_keeps_the_ends_and_darkens_the_middle() {
+        let correction = ColorCorrection::new(2.2, [1.0, 1.0, 1.0], None);
+        assert_eq!(correct(&correction, 0x000000), [0, 0, 0]);
+        assert_eq!(correct(&correction, 0xFFFFFF), [255, 255, 255]);
+        // (128 / 255) ^ 2.2 * 255 = 55.98
+        assert_eq!(correct(&correction, 0x808080), [56, 56, 56]);
+    }
+
+    #[test]
+    fn linear_gamma_leaves_colours_alone() {
+        let correction = ColorCorrection::new(1.0, [1.0, 1.0, 1.0], None);
+        assert_eq!(correct(&correction, 0x123456), [0x12, 0x34, 0x56]);
+    }
+
+    #[test]
+    fn white_balance_scales_each_colour() {
+        let correction = ColorCorrection::new(1.0, [0.5, 1.0, 0.0], None);
+        assert_eq!(correct(&correction, 0xFFFFFF), [128, 255, 0]);
+    }
+
+    #[test]
+    fn colour_temperature_is_white_at_6600k_and_red_when_warm() {
+        assert_eq!(temperature_to_rgb(6600), [1.0, 1.0, 1.0]);
+        let [red, green, blue] = temperature_to_rgb(2700);
+        assert_eq!(red, 1.0);
+        assert!(green < red && blue < green);
+    }
+}
@@ -0,0 +1,298 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::Serialize;
+use serde_json::json;
+use tokio::net::UdpSocket;
+
+use super::converter::rgb_to_u32;
+use super::dmx::receive_packet;
+use super::live::{LiveFrame, LiveInput, LiveSource};
+use super::mapping::PixelMap;
+
+use crate::config::{DdpConfig, LightsConfig};
+use crate::database::initialize::AppState;
+use crate::thread_utils::NotifyChecker;
+
+const HEADER_LENGTH: usize = 10;
+/// The header is four bytes longer when it carries a timecode
+const TIMECODE_LENGTH: usize = 4;
+/// Most data a packet can carry, 480 RGB pixels
+const MAX_DATA: usize = 1440;
+const MAX_PACKET: usize = HEADER_LENGTH + TIMECODE_LENGTH + MAX_DATA;
+
+const VERSION_MASK: u8 = 0xC0;
+const VERSION_1: u8 = 0x40;
+const FLAG_TIMECODE: u8 = 0x10;
+const FLAG_QUERY: u8 = 0x02;
+const FLAG_PUSH: u8 = 0x01;
+
+/// Destinations that mean the pixels, anything else is config, status or DMX
+const DISPLAY_DESTINATIONS: [u8; 3] = [0, 1, 255];
+
+/// What the DDP receiver has been sent since it started
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DdpStats {
+    pub packets: u64,
+    /// Pushes handed to the light loop
+    pub frames: u64,
+    /// Packets that were not used, because they were malformed, a query or for another
+    /// destination
+    pub drops: u64,
+    /// Bytes left out because they ran past the end of the LEDs, the rest of those packets
+    /// is still shown
+    pub overrun_bytes: u64,
+}
+
+pub type SharedDdpStats = Arc<Mutex<DdpStats>>;
+
+/// The parts of a DDP packet the receiver uses
+#[derive(Debug)]
+struct Packet<'a> {
+    flags: u8,
+    destination: u8,
+    /// In bytes from the start of the buffer
+    offset: usize,
+    data: &'a [u8],
+}
+
+fn parse(packet: &[u8]) -> Option<Packet<'_>> {
+    let flags = *packet.first()?;
+    if flags & VERSION_MASK != VERSION_1 || packet.len() < HEADER_LENGTH {
+        return None;
+    }
+    let start = match flags & FLAG_TIMECODE {
+        0 => HEADER_LENGTH,
+        _ => HEADER_LENGTH + TIMECODE_LENGTH,
+    };
+    let length = u16::from_be_bytes([packet[8], packet[9]]) as usize;
+    return Some(Packet {
+        flags: flags,
+        destination: packet[3],
+        offset: u32::from_be_bytes(packet[4..8].try_into().unwrap()) as usize,
+        data: packet.get(start..start + length)?,
+    });
+}
+
+/// Copies as much of the packet's data as fits into `leds`, returning how many bytes ran past
+/// the end
+fn write_packet(leds: &mut [u8], packet: &Packet) -> usize {
+    let start = packet.offset.min(leds.len());
+    let fits = packet.data.len().min(leds.len() - start);
+    leds[start..start + fits].copy_from_slice(&packet.data[..fits]);
+    return packet.data.len() - fits;
+}
+
+/// The frame the LEDs make up, `targets` is from `led_targets`
+fn frame_from(leds: &[u8], targets: &[Option<usize>], frame_size: usize) -> Vec<u32> {
+    let mut pixels = vec![0u32; frame_size];
+    for (rgb, target) in leds.chunks_exact(3).zip(targets.iter()) {
+        if let Some(pixel) = target {
+            pixels[*pixel] = rgb_to_u32(rgb[0], rgb[1], rgb[2]);
+        }
+    }
+    return pixels;
+}
+
+/// The pixel of the frame each LED shows, with the channels one after the other in the order
+/// they are in the config. None for LEDs no segment covers.
+fn led_targets(lights: &LightsConfig) -> Vec<Option<usize>> {
+    let pixel_map = PixelMap::from_config(lights);
+    return lights
+        .channels
+        .iter()
+        .enumerate()
+        .flat_map(|(channel, config)| {
+            let pixel_map = &pixel_map;
+            (0..config.led_count).map(move |led| pixel_map.frame_index(channel, led))
+        })
+        .collect();
+}
+
+/// Listens for DDP, which addresses every LED as one run of RGB bytes, channels one after the
+/// other in the order they are in the config. Data is collected until a packet with the push
+/// flag comes in, then the whole buffer is handed to the light loop as a live frame.
+pub async fn run_receiver(
+    config: DdpConfig,
+    lights: LightsConfig,
+    stats: SharedDdpStats,
+    live: tokio::sync::mpsc::Sender<LiveInput>,
+    shutdown: NotifyChecker,
+) {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.port)).await {
+        Ok(socket) => socket,
+        Err(error) => {
+            println!("DDP: could not listen on port {}: {error}", config.port);
+            return;
+        }
+    };
+    let targets = led_targets(&lights);
+    let mut leds = vec![0u8; targets.len() * 3];
+    println!(
+        "DDP: listening on port {} for {} LEDs",
+        config.port,
+        targets.len()
+    );
+
+    let timeout = Duration::from_millis(config.timeout_ms.unwrap_or(lights.live_timeout_ms));
+    let frame_size = lights.frame_size();
+    let mut buffer = [0u8; MAX_PACKET];
+    while let Some((length, _)) = receive_packet(&socket, &mut buffer, &shutdown, "DDP").await {
+        let packet = match parse(&buffer[..length]) {
+            Some(packet)
+                if packet.flags & FLAG_QUERY == 0
+                    && DISPLAY_DESTINATIONS.contains(&packet.destination) =>
+            {
+                packet
+            }
+            _ => {
+                let mut stats = stats.lock().unwrap();
+                stats.packets += 1;
+                stats.drops += 1;
+                continue;
+            }
+        };
+        let overrun = write_packet(&mut leds, &packet);
+        let push = packet.flags & FLAG_PUSH != 0;
+        {
+            let mut stats = stats.lock().unwrap();
+            stats.packets += 1;
+            stats.overrun_bytes += overrun as u64;
+            if push {
+                stats.frames += 1;
+            }
+        }
+        if !push {
+            continue;
+        }
+
+        let frame = LiveFrame {
+            pixels: frame_from(&leds, &targets, frame_size),
+            source: LiveSource::Ddp,
+            timeout: timeout,
+        };
+        if live.send(LiveInput::Frame(frame)).await.is_err() {
+            break;
+        }
+    }
+    println!("DDP: Stopped");
+}
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new().route("/", get(get_stats)).with_state(state);
+
+    index.insert("/ddp", "GET");
+    return app;
+}
+
+/// Returns how many packets and frames the DDP receiver has taken and how many packets it
+/// could not use
+pub async fn get_stats(State(state): State<Arc<AppState>>) -> Response {
+    let stats = match &state.ddp {
+        Some(stats) => stats.lock().unwrap().clone(),
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                json!({"error": "add a [ddp] section to the config to receive DDP"}).to_string(),
+            )
+                .into_response()
+        }
+    };
+    return serde_json::to_string(&stats).unwrap().into_response();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(flags: u8, offset: u32, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![flags, 0, 0, 1];
+        packet.extend_from_slice(&offset.to_be_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        return packet;
+    }
+
+    fn packet(flags: u8, offset: u32, data: &[u8]) -> Vec<u8> {
+        let mut packet = header(flags, offset, data);
+        packet.extend_from_slice(data);
+        return packet;
+    }
+
+    #[test]
+    fn parses_the_push_flag_and_offset() {
+        let bytes = packet(VERSION_1 | FLAG_PUSH, 6, &[1, 2, 3]);
+        let parsed = parse(&bytes).unwrap();
+        assert_ne!(parsed.flags & FLAG_PUSH, 0);
+        assert_eq!(parsed.destination, 1);
+        assert_eq!(parsed.offset, 6);
+        assert_eq!(parsed.data, &[1, 2, 3]);
+
+        let bytes = packet(VERSION_1, 0, &[1, 2, 3]);
+        assert_eq!(parse(&bytes).unwrap().flags & FLAG_PUSH, 0);
+    }
+
+    #[test]
+    fn skips_the_timecode() {
+        let mut bytes = header(VERSION_1 | FLAG_TIMECODE, 0, &[1, 2, 3]);
+        bytes.extend_from_slice(&[0xAA; TIMECODE_LENGTH]);
+        bytes.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(parse(&bytes).unwrap().data, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_other_versions() {
+        let bytes = packet(VERSION_1, 0, &[1, 2, 3]);
+        assert!(parse(&bytes[..bytes.len() - 1]).is_none());
+        assert!(parse(&bytes[..HEADER_LENGTH - 1]).is_none());
+        assert!(parse(&[]).is_none());
+        assert!(parse(&packet(0x80, 0, &[1, 2, 3])).is_none());
+    }
+
+    #[test]
+    fn data_past_the_end_is_left_out() {
+        let mut leds = vec![0u8; 6];
+        let bytes = packet(VERSION_1, 3, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(write_packet(&mut leds, &parse(&bytes).unwrap()), 3);
+        assert_eq!(leds, vec![0, 0, 0, 1, 2, 3]);
+
+        let bytes = packet(VERSION_1, 9, &[7, 8, 9]);
+        assert_eq!(write_packet(&mut leds, &parse(&bytes).unwrap()), 3);
+        assert_eq!(leds, vec![0, 0, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn pushes_the_leds_as_a_frame() {
+        let lights: LightsConfig = toml::from_str(
+            r#"
+            [[channels]]
+            name = "test"
+            pin = 12
+            led_count = 2
+            strip_type = "ws2811_rgb"
+            brightness = 255
+            "#,
+        )
+        .unwrap();
+        let targets = led_targets(&lights);
+        let mut leds = vec![0u8; targets.len() * 3];
+        let first = packet(VERSION_1, 0, &[0x11, 0x22, 0x33]);
+        write_packet(&mut leds, &parse(&first).unwrap());
+        let second = packet(VERSION_1 | FLAG_PUSH, 3, &[0x44, 0x55, 0x66]);
+        write_packet(&mut leds, &parse(&second).unwrap());
+        assert_eq!(
+            frame_from(&leds, &targets, lights.frame_size()),
+            vec![0x112233, 0x445566]
+        );
+    }
+}
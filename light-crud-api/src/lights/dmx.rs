@@ -0,0 +1,230 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::SocketAddr,
+};
+
+use tokio::net::UdpSocket;
+
+use super::converter::rgb_to_u32;
+use super::mapping::PixelMap;
+
+use crate::config::{LightsConfig, UniverseConfig};
+use crate::thread_utils::NotifyChecker;
+
+/// Slots in a DMX universe
+pub const UNIVERSE_SLOTS: usize = 512;
+
+/// Waits for the next packet on `socket`, None once the server is shutting down. `protocol`
+/// names the receiver in errors.
+pub async fn receive_packet(
+    socket: &UdpSocket,
+    buffer: &mut [u8],
+    shutdown: &NotifyChecker,
+    protocol: &str,
+) -> Option<(usize, SocketAddr)> {
+    while !shutdown.is_notified() {
+        tokio::select! {
+            received = socket.recv_from(buffer) => match received {
+                Ok(received) => return Some(received),
+                Err(error) => println!("{protocol}: {error}"),
+            },
+            _ = shutdown.wait() => {}
+        }
+    }
+    return None;
+}
+
+/// Whether a packet numbered `sequence` is out of order after `last`. Anything up to 20
+/// behind is a late packet rather than the sender restarting, and the count wraps from 255 to 0.
+pub fn is_late_sequence(sequence: u8, last: u8) -> bool {
+    return (-19..=0).contains(&(sequence.wrapping_sub(last) as i8));
+}
+
+/// Where the LEDs in each DMX universe end up in the frame.
+///
+/// Universes address the physical channels, so each LED is traced back through the segments
+/// to the pixel of the frame that feeds it. LEDs no segment covers are not shown. Without
+/// segments every channel mirrors the start of the frame, so channels sent different data
+/// show whichever universe arrived last.
+#[derive(Clone, Debug)]
+pub struct UniverseMap {
+    /// For each universe, the slot of each LED's red and the pixel of the frame it sets
+    universes: BTreeMap<u16, Vec<(usize, usize)>>,
+    frame_size: usize,
+}
+
+impl UniverseMap {
+    /// Expects the outputs to have been validated
+    pub fn new(lights: &LightsConfig, outputs: &[UniverseConfig]) -> Self {
+        let pixel_map = PixelMap::from_config(lights);
+        let mut universes: BTreeMap<u16, Vec<(usize, usize)>> = BTreeMap::new();
+        for output in outputs {
+            let channel = lights.channel_index(&output.channel).unwrap();
+            let mut universe = output.universe;
+            let mut slot = output.start_channel as usize - 1;
+            for led in 0..lights.channels[channel].led_count {
+                // LEDs are not split across universes
+                if slot + 3 > UNIVERSE_SLOTS {
+                    universe += 1;
+                    slot = 0;
+                }
+                if let Some(pixel) = pixel_map.frame_index(channel, led) {
+                    universes.entry(universe).or_default().push((slot, pixel));
+                }
+                slot += 3;
+            }
+        }
+        return UniverseMap {
+            universes: universes,
+            frame_size: lights.frame_size(),
+        };
+    }
+
+    pub fn universes(&self) -> impl Iterator<Item = u16> + '_ {
+        return self.universes.keys().copied();
+    }
+
+    pub fn contains(&self, universe: u16) -> bool {
+        return self.universes.contains_key(&universe);
+    }
+
+    /// Copies the LEDs in `slots`, the DMX data after the start code, into `frame`. LEDs past
+    /// the end of a short universe are left as they were.
+    pub fn write(&self, universe: u16, slots: &[u8], frame: &mut [u32]) {
+        let leds = match self.universes.get(&universe) {
+            Some(leds) => leds,
+            None => return,
+        };
+        for (slot, pixel) in leds.iter() {
+            if let Some(rgb) = slots.get(*slot..*slot + 3) {
+                frame[*pixel] = rgb_to_u32(rgb[0], rgb[1], rgb[2]);
+            }
+        }
+    }
+}
+
+/// Builds whole frames out of universes that arrive one packet at a time.
+///
+/// A frame is ready once every mapped universe has come in, or when a universe comes round
+/// again before the rest have, so a sender that only drives some of the universes still gets
+/// its frames shown.
+#[derive(Clone, Debug)]
+pub struct FrameAssembler {
+    map: UniverseMap,
+    frame: Vec<u32>,
+    /// Universes written since the last frame was handed out
+    pending: BTreeSet<u16>,
+}
+
+impl FrameAssembler {
+    pub fn new(map: UniverseMap) -> Self {
+        FrameAssembler {
+            frame: vec![0; map.frame_size],
+            map: map,
+            pending: BTreeSet::new(),
+        }
+    }
+
+    pub fn map(&self) -> &UniverseMap {
+        return &self.map;
+    }
+
+    /// Takes one universe of DMX data, returning a frame when one is ready
+    pub fn receive(&mut self, universe: u16, slots: &[u8]) -> Option<Vec<u32>> {
+        if !self.map.contains(universe) {
+            return None;
+        }
+        let mut ready = None;
+        if !self.pending.insert(universe) {
+            ready = Some(self.frame.clone());
+            self.pending.clear();
+            self.pending.insert(universe);
+        }
+        self.map.write(universe, slots, &mut self.frame);
+        if self.pending.len() == self.map.universes.len() {
+            self.pending.clear();
+            ready = Some(self.frame.clone());
+        }
+        return ready;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_from(start_channel: u16) -> UniverseMap {
+        let lights: LightsConfig = toml::from_str(
+            r#"
+            [[channels]]
+            name = "test"
+            pin = 12
+            led_count = 3
+            strip_type = "ws2811_rgb"
+            brightness = 255
+            "#,
+        )
+        .unwrap();
+        let output = UniverseConfig {
+            channel: String::from("test"),
+            universe: 1,
+            start_channel: start_channel,
+        };
+        return UniverseMap::new(&lights, &[output]);
+    }
+
+    #[test]
+    fn a_led_in_the_last_three_slots_stays_in_the_universe() {
+        let map = map_from(510);
+        assert_eq!(map.universes[&1], vec![(509, 0)]);
+        assert_eq!(map.universes[&2], vec![(0, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn a_led_that_would_span_two_universes_starts_the_next() {
+        let map = map_from(511);
+        assert_eq!(map.universes.get(&1), None);
+        assert_eq!(map.universes[&2], vec![(0, 0), (3, 1), (6, 2)]);
+    }
+
+    #[test]
+    fn a_frame_is_ready_once_every_universe_is_in() {
+        let mut assembler = FrameAssembler::new(map_from(510));
+        let mut first = vec![0u8; UNIVERSE_SLOTS];
+        first[509..].copy_from_slice(&[0x11, 0x22, 0x33]);
+        assert_eq!(assembler.receive(1, &first), None);
+        assert_eq!(
+            assembler.receive(2, &[0x44, 0x55, 0x66, 0x77, 0x88, 0x99]),
+            Some(vec![0x112233, 0x445566, 0x778899])
+        );
+        // a universe that is not mapped is ignored
+        assert_eq!(assembler.receive(3, &[0xFF; 6]), None);
+    }
+
+    #[test]
+    fn a_universe_coming_round_again_hands_out_the_frame() {
+        let mut assembler = FrameAssembler::new(map_from(510));
+        let mut first = vec![0u8; UNIVERSE_SLOTS];
+        first[509..].copy_from_slice(&[0x11, 0x22, 0x33]);
+        assert_eq!(assembler.receive(1, &first), None);
+        first[509..].copy_from_slice(&[0xAA, 0xBB, 0xCC]);
+        // the frame from before this packet, which then starts the next one
+        assert_eq!(assembler.receive(1, &first), Some(vec![0x112233, 0, 0]));
+        // a short universe leaves the LEDs past its end as they were
+        assert_eq!(
+            assembler.receive(2, &[0x44, 0x55, 0x66]),
+            Some(vec![0xAABBCC, 0x445566, 0])
+        );
+    }
+
+    #[test]
+    fn late_sequences_wrap_from_255_to_0() {
+        assert!(!is_late_sequence(0, 255));
+        assert!(!is_late_sequence(1, 0));
+        assert!(is_late_sequence(255, 0));
+        assert!(is_late_sequence(7, 7));
+        assert!(is_late_sequence(236, 255));
+        // too far behind to be late, the sender has restarted
+        assert!(!is_late_sequence(235, 255));
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use tokio::net::UdpSocket;
+
+use super::dmx::{is_late_sequence, receive_packet, FrameAssembler, UniverseMap, UNIVERSE_SLOTS};
+use super::live::{LiveFrame, LiveInput, LiveSource};
+
+use crate::config::{E131Config, LightsConfig};
+use crate::thread_utils::NotifyChecker;
+
+/// "ASC-E1.17" padded with nulls, at the start of every packet after the preamble
+const ACN_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+/// Offset of the DMX start code, the slots follow it
+const START_CODE_OFFSET: usize = 125;
+/// A data packet with a full universe
+const MAX_PACKET: usize = START_CODE_OFFSET + 1 + UNIVERSE_SLOTS;
+
+/// Sent for visualisers, not for the lights
+const OPTION_PREVIEW_DATA: u8 = 0x80;
+/// The source is going away and will not send anything else
+const OPTION_STREAM_TERMINATED: u8 = 0x40;
+
+/// The parts of an E1.31 data packet the receiver uses
+#[derive(Debug)]
+struct DataPacket<'a> {
+    /// Identifies the sender
+    cid: [u8; 16],
+    source_name: String,
+    priority: u8,
+    sequence: u8,
+    options: u8,
+    universe: u16,
+    /// DMX data after the start code
+    slots: &'a [u8],
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> u16 {
+    return u16::from_be_bytes([packet[offset], packet[offset + 1]]);
+}
+
+fn read_u32(packet: &[u8], offset: usize) -> u32 {
+    return u32::from_be_bytes(packet[offset..offset + 4].try_into().unwrap());
+}
+
+/// Reads a data packet, None for anything else such as sync or universe discovery packets,
+/// or DMX with an alternate start code
+fn parse(packet: &[u8]) -> Option<DataPacket<'_>> {
+    if packet.len() <= START_CODE_OFFSET
+        || read_u16(packet, 0) != 0x0010
+        || &packet[4..16] != ACN_IDENTIFIER
+        || read_u32(packet, 18) != VECTOR_ROOT_E131_DATA
+        || read_u32(packet, 40) != VECTOR_E131_DATA_PACKET
+        || packet[117] != VECTOR_DMP_SET_PROPERTY
+        || packet[START_CODE_OFFSET] != 0
+    {
+        return None;
+    }
+    // the count includes the start code
+    let slot_count = (read_u16(packet, 123) as usize).checked_sub(1)?;
+    let slots = packet.get(START_CODE_OFFSET + 1..START_CODE_OFFSET + 1 + slot_count)?;
+    let name = &packet[44..108];
+    let name_length = name
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(name.len());
+    return Some(DataPacket {
+        cid: packet[22..38].try_into().unwrap(),
+        source_name: String::from_utf8_lossy(&name[..name_length]).to_string(),
+        priority: packet[108],
+        sequence: packet[111],
+        options: packet[112],
+        universe: read_u16(packet, 113),
+        slots: &slots[..slots.len().min(UNIVERSE_SLOTS)],
+    });
+}
+
+/// A sender of one universe
+#[derive(Clone, Debug)]
+struct Source {
+    name: String,
+    priority: u8,
+    sequence: u8,
+    last_seen: Instant,
+}
+
+/// Picks which sender to take each universe from. Only the highest priority sender still
+/// sending is listened to, senders at the same priority are taken as they come.
+#[derive(Debug, Default)]
+struct Arbiter {
+    sources: HashMap<(u16, [u8; 16]), Source>,
+}
+
+impl Arbiter {
+    /// Whether the packet's data should be shown
+    fn accept(&mut self, packet: &DataPacket, timeout: Duration, now: Instant) -> bool {
+        self.sources
+            .retain(|_, source| now.duration_since(source.last_seen) < timeout);
+        let key = (packet.universe, packet.cid);
+        if packet.options & OPTION_STREAM_TERMINATED != 0 {
+            if let Some(source) = self.sources.remove(&key) {
+                println!(
+                    "E1.31: {} stopped sending universe {}",
+                    source.name, packet.universe
+                );
+            }
+            return false;
+        }
+        match self.sources.get(&key) {
+            Some(source) if is_late_sequence(packet.sequence, source.sequence) => return false,
+            Some(_) => {}
+            None => println!(
+                "E1.31: {} is sending universe {} at priority {}",
+                packet.source_name, packet.universe, packet.priority
+            ),
+        }
+        self.sources.insert(
+            key,
+            Source {
+                name: packet.source_name.clone(),
+                priority: packet.priority,
+                sequence: packet.sequence,
+                last_seen: now,
+            },
+        );
+        let highest = self
+            .sources
+            .iter()
+            .filter(|((universe, _), _)| *universe == packet.universe)
+            .map(|(_, source)| source.priority)
+            .max()
+            .unwrap_or(0);
+        return packet.priority >= highest;
+    }
+}
+
+/// Multicast group a universe is sent to
+fn multicast_address(universe: u16) -> Ipv4Addr {
+    let [high, low] = universe.to_be_bytes();
+    return Ipv4Addr::new(239, 255, high, low);
+}
+
+/// Listens for E1.31 and hands every complete frame to the light loop as live frames, which
+/// show in place of the animation until the data stops for the timeout
+pub async fn run_receiver(
+    config: E131Config,
+    lights: LightsConfig,
+    live: tokio::sync::mpsc::Sender<LiveInput>,
+    shutdown: NotifyChecker,
+) {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.port)).await {
+        Ok(socket) => socket,
+        Err(error) => {
+            println!("E1.31: could not listen on port {}: {error}", config.port);
+            return;
+        }
+    };
+    let mut assembler = FrameAssembler::new(UniverseMap::new(&lights, &config.outputs));
+    if config.multicast {
+        let interface: Ipv4Addr = config.interface.parse().unwrap();
+        for universe in assembler.map().universes() {
+            if let Err(error) = socket.join_multicast_v4(multicast_address(universe), interface) {
+                println!(
+                    "E1.31: could not join the multicast group for universe {universe}: {error}"
+                );
+            }
+        }
+    }
+    let universes: Vec<u16> = assembler.map().universes().collect();
+    println!(
+        "E1.31: listening on port {} for universes {universes:?}",
+        config.port
+    );
+
+    let timeout = Duration::from_millis(config.timeout_ms.unwrap_or(lights.live_timeout_ms));
+    let mut arbiter = Arbiter::default();
+    let mut buffer = [0u8; MAX_PACKET];
+    while let Some((length, _)) = receive_packet(&socket, &mut buffer, &shutdown, "E1.31").await {
+        let packet = match parse(&buffer[..length]) {
+            Some(packet) if packet.options & OPTION_PREVIEW_DATA == 0 => packet,
+            _ => continue,
+        };
+        if !assembler.map().contains(packet.universe)
+            || !arbiter.accept(&packet, timeout, Instant::now())
+        {
+            continue;
+        }
+        if let Some(pixels) = assembler.receive(packet.universe, packet.slots) {
+            let frame = LiveFrame {
+                pixels: pixels,
+                source: LiveSource::E131,
+                timeout: timeout,
+            };
+            if live.send(LiveInput::Frame(frame)).await.is_err() {
+                break;
+            }
+        }
+    }
+    println!("E1.31: Stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(2500);
+
+    /// A data packet as a sender would put it on the wire
+    fn packet(universe: u16, priority: u8, sequence: u8, slots: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; START_CODE_OFFSET + 1];
+        packet[0..2].copy_from_slice(&0x0010u16.to_be_bytes());
+        packet[4..16].copy_from_slice(ACN_IDENTIFIER);
+        packet[18..22].copy_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+        packet[22..38].copy_from_slice(&[7; 16]);
+        packet[40..44].copy_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+        packet[44..51].copy_from_slice(b"console");
+        packet[108] = priority;
+        packet[111] = sequence;
+        packet[113..115].copy_from_slice(&universe.to_be_bytes());
+        packet[117] = VECTOR_DMP_SET_PROPERTY;
+        packet[123..125].copy_from_slice(&(slots.len() as u16 + 1).to_be_bytes());
+        packet.extend_from_slice(slots);
+        return packet;
+    }
+
+    fn from(cid: u8, priority: u8, sequence: u8) -> DataPacket<'static> {
+        return DataPacket {
+            cid: [cid; 16],
+            source_name: format!("console {cid}"),
+            priority: priority,
+            sequence: sequence,
+            options: 0,
+            universe: 1,
+            slots: &[],
+        };
+    }
+
+    #[test]
+    fn parses_a_data_packet() {
+        let bytes = packet(3, 150, 42, &[1, 2, 3, 4, 5, 6]);
+        let parsed = parse(&bytes).unwrap();
+        assert_eq!(parsed.cid, [7; 16]);
+        assert_eq!(parsed.source_name, "console");
+        assert_eq!(parsed.priority, 150);
+        assert_eq!(parsed.sequence, 42);
+        assert_eq!(parsed.universe, 3);
+        assert_eq!(parsed.slots, &[1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn rejects_a_truncated_packet() {
+        let bytes = packet(3, 100, 0, &[1, 2, 3, 4, 5, 6]);
+        // shorter than the slot count says
+        assert!(parse(&bytes[..bytes.len() - 1]).is_none());
+        // cut off in the header
+        assert!(parse(&bytes[..START_CODE_OFFSET]).is_none());
+    }
+
+    #[test]
+    fn rejects_other_packets() {
+        let mut bytes = packet(3, 100, 0, &[1, 2, 3]);
+        bytes[4..16].copy_from_slice(b"ASC-E1.18\0\0\0");
+        assert!(parse(&bytes).is_none());
+
+        let mut bytes = packet(3, 100, 0, &[1, 2, 3]);
+        // an alternate start code, e.g. text or system information
+        bytes[START_CODE_OFFSET] = 0x17;
+        assert!(parse(&bytes).is_none());
+    }
+
+    #[test]
+    fn a_higher_priority_sender_takes_over() {
+        let mut arbiter = Arbiter::default();
+        let now = Instant::now();
+        assert!(arbiter.accept(&from(1, 100, 0), TIMEOUT, now));
+        assert!(arbiter.accept(&from(2, 150, 0), TIMEOUT, now));
+        assert!(!arbiter.accept(&from(1, 100, 1), TIMEOUT, now));
+        assert!(arbiter.accept(&from(2, 150, 1), TIMEOUT, now));
+
+        // once the higher priority sender stops, the other one is shown again
+        let later = now + TIMEOUT + Duration::from_millis(1);
+        assert!(arbiter.accept(&from(1, 100, 2), TIMEOUT, later));
+    }
+
+    #[test]
+    fn a_terminated_stream_hands_back_to_the_other_sender() {
+        let mut arbiter = Arbiter::default();
+        let now = Instant::now();
+        assert!(arbiter.accept(&from(1, 100, 0), TIMEOUT, now));
+        assert!(arbiter.accept(&from(2, 150, 0), TIMEOUT, now));
+        let mut terminated = from(2, 150, 1);
+        terminated.options = OPTION_STREAM_TERMINATED;
+        assert!(!arbiter.accept(&terminated, TIMEOUT, now));
+        assert!(arbiter.accept(&from(1, 100, 1), TIMEOUT, now));
+    }
+
+    #[test]
+    fn the_sequence_wraps_from_255_to_0() {
+        let mut arbiter = Arbiter::default();
+        let now = Instant::now();
+        assert!(arbiter.accept(&from(1, 100, 254), TIMEOUT, now));
+        assert!(arbiter.accept(&from(1, 100, 255), TIMEOUT, now));
+        assert!(arbiter.accept(&from(1, 100, 0), TIMEOUT, now));
+        // a late packet from before the wrap
+        assert!(!arbiter.accept(&from(1, 100, 255), TIMEOUT, now));
+        assert!(arbiter.accept(&from(1, 100, 1), TIMEOUT, now));
+    }
+}
@@ -0,0 +1,198 @@
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::converter::rgb_to_u32;
+
+use crate::database::initialize::AppState;
+
+/// Where live frames are coming from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveSource {
+    /// `POST /live/frame`
+    Http,
+    /// An E1.31 (sACN) sender such as xLights
+    E131,
+    ArtNet,
+    Ddp,
+}
+
+/// A frame pushed straight to the light loop, it is never stored
+#[derive(Clone, Debug)]
+pub struct LiveFrame {
+    pub pixels: Vec<u32>,
+    pub source: LiveSource,
+    /// How long to keep showing it with nothing newer before going back to the animation
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub enum LiveInput {
+    Frame(LiveFrame),
+    /// Go back to the animation straight away
+    Stop,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LiveStatus {
+    pub source: LiveSource,
+    pub frames_received: u64,
+    pub pixels: usize,
+    /// Time since the last frame came in
+    pub idle_ms: u128,
+    pub timeout_ms: u128,
+}
+
+/// Live frames being shown over the top of the animation, which waits underneath
+#[derive(Clone, Debug)]
+pub struct LiveOverride {
+    pub frame: Vec<u32>,
+    source: LiveSource,
+    received: Instant,
+    timeout: Duration,
+    frames_received: u64,
+}
+
+impl LiveOverride {
+    pub fn new(frame: LiveFrame, now: Instant) -> Self {
+        LiveOverride {
+            frame: frame.pixels,
+            source: frame.source,
+            received: now,
+            timeout: frame.timeout,
+            frames_received: 1,
+        }
+    }
+
+    pub fn update(&mut self, frame: LiveFrame, now: Instant) {
+        self.frame = frame.pixels;
+        self.source = frame.source;
+        self.received = now;
+        self.timeout = frame.timeout;
+        self.frames_received += 1;
+    }
+
+    pub fn source(&self) -> LiveSource {
+        return self.source;
+    }
+
+    pub fn expires_at(&self) -> Instant {
+        return self.received + self.timeout;
+    }
+
+    pub fn status(&self, now: Instant) -> LiveStatus {
+        return LiveStatus {
+            source: self.source,
+            frames_received: self.frames_received,
+            pixels: self.frame.len(),
+            idle_ms: now.duration_since(self.received).as_millis(),
+            timeout_ms: self.timeout.as_millis(),
+        };
+    }
+}
+
+/// Packs red, green and blue bytes into the `0xRRGGBB` colours frames are stored as
+pub fn pixels_from_rgb(bytes: &[u8]) -> Vec<u32> {
+    return bytes
+        .chunks_exact(3)
+        .map(|rgb| rgb_to_u32(rgb[0], rgb[1], rgb[2]))
+        .collect();
+}
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/frame", post(post_frame))
+        .route("/stop", post(stop))
+        .with_state(state);
+
+    index.insert("/live/frame", "POST");
+    index.insert("/live/stop", "POST");
+    return app;
+}
+
+/// Optional query parameters for a live frame, e.g. `?timeout_ms=10000`
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LiveOptions {
+    /// Overrides `lights.live_timeout_ms`
+    pub timeout_ms: Option<u64>,
+}
+
+/// A 400 response with `error` as the JSON error message
+pub fn bad_request(error: String) -> Response {
+    return (StatusCode::BAD_REQUEST, Json(json!({"error": error}))).into_response();
+}
+
+/// Shows a frame straight away without storing it.
+///
+/// The body is either a JSON array of `0xRRGGBB` colours, or with
+/// `Content-Type: application/octet-stream`, three bytes of red, green and blue per pixel.
+/// Pixels past the end of the body are black. The animation carries on once no frame has come
+/// in for the timeout.
+pub async fn post_frame(
+    Query(options): Query<LiveOptions>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let binary = headers
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "application/octet-stream");
+    let pixels = match binary {
+        true if !body.len().is_multiple_of(3) => {
+            return bad_request(format!(
+                "a binary frame needs three bytes per pixel, got {} bytes",
+                body.len()
+            ))
+        }
+        true => pixels_from_rgb(&body),
+        false => match serde_json::from_slice::<Vec<u32>>(&body) {
+            Ok(pixels) => pixels,
+            Err(error) => {
+                return bad_request(format!(
+                    "expected a JSON array of colours like [16711680, 65280]: {error}"
+                ))
+            }
+        },
+    };
+    let frame_size = state.lights.frame_size();
+    if pixels.len() > frame_size {
+        return bad_request(format!(
+            "the frame has {} pixels, the lights only have {frame_size}",
+            pixels.len()
+        ));
+    }
+    let timeout = Duration::from_millis(options.timeout_ms.unwrap_or(state.lights.live_timeout_ms));
+    let count = pixels.len();
+    state
+        .send_to_live
+        .send(LiveInput::Frame(LiveFrame {
+            pixels: pixels,
+            source: LiveSource::Http,
+            timeout: timeout,
+        }))
+        .await
+        .unwrap();
+    return json!({"pixels": count, "timeout_ms": timeout.as_millis()})
+        .to_string()
+        .into_response();
+}
+
+/// Stops showing live frames and goes back to the animation
+pub async fn stop(State(state): State<Arc<AppState>>) -> Response {
+    state.send_to_live.send(LiveInput::Stop).await.unwrap();
+    return json!({"live": "stopped"}).to_string().into_response();
+}
@@ -0,0 +1,148 @@
+use crate::config::LightsConfig;
+
+/// A segment from the config with the channel name resolved to its index
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub channel: usize,
+    pub start: usize,
+    pub length: usize,
+    pub offset: usize,
+    pub reversed: bool,
+}
+
+impl Segment {
+    /// LED on the channel that shows the `index`th pixel of this segment
+    pub fn physical_index(&self, index: usize) -> usize {
+        if self.reversed {
+            return self.offset + self.length - 1 - index;
+        }
+        return self.offset + index;
+    }
+}
+
+/// Maps the pixels of a frame onto the LEDs of the physical channels.
+///
+/// Stored frames treat the whole installation as one strip, the map decides which channel and
+/// LED each pixel ends up on.
+#[derive(Clone, Debug)]
+pub struct PixelMap {
+    pub segments: Vec<Segment>,
+    pub frame_size: usize,
+}
+
+impl PixelMap {
+    /// Builds the map from `lights.segments`, falling back to every channel mirroring the start
+    /// of the frame when no segments are configured. Expects the config to have been validated.
+    pub fn from_config(lights: &LightsConfig) -> Self {
+        let segments = if lights.segments.is_empty() {
+            lights
+                .channels
+                .iter()
+                .enumerate()
+                .map(|(index, channel)| Segment {
+                    channel: index,
+                    start: 0,
+                    length: channel.led_count,
+                    offset: 0,
+                    reversed: false,
+                })
+                .collect()
+        } else {
+            lights
+                .segments
+                .iter()
+                .map(|segment| Segment {
+                    channel: lights.channel_index(&segment.channel).unwrap(),
+                    start: segment.start,
+                    length: segment.length,
+                    offset: segment.offset,
+                    reversed: segment.reversed,
+                })
+                .collect()
+        };
+        return PixelMap {
+            segments: segments,
+            frame_size: lights.frame_size(),
+        };
+    }
+
+    /// The pixel of the frame shown on `led` of `channel`, None if no segment covers it
+    pub fn frame_index(&self, channel: usize, led: usize) -> Option<usize> {
+        return self
+            .segments
+            .iter()
+            .filter(|segment| segment.channel == channel)
+            .filter(|segment| (segment.offset..segment.offset + segment.length).contains(&led))
+            .map(|segment| match segment.reversed {
+                true => segment.start + segment.offset + segment.length - 1 - led,
+                false => segment.start + led - segment.offset,
+            })
+            .next();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10 pixels, the first 6 forwards on `a` after 2 unused LEDs, the last 4 reversed on `b`
+    fn map() -> PixelMap {
+        let lights: LightsConfig = toml::from_str(
+            r#"
+            [[channels]]
+            name = "a"
+            pin = 12
+            led_count = 8
+            strip_type = "ws2811_rgb"
+            brightness = 255
+
+            [[channels]]
+            name = "b"
+            pin = 19
+            led_count = 4
+            strip_type = "ws2811_rgb"
+            brightness = 255
+
+            [[segments]]
+            channel = "a"
+            start = 0
+            length = 6
+            offset = 2
+
+            [[segments]]
+            channel = "b"
+            start = 6
+            length = 4
+            reversed = true
+            "#,
+        )
+        .unwrap();
+        return PixelMap::from_config(&lights);
+    }
+
+    #[test]
+    fn reversed_segment_runs_from_the_far_end() {
+        let map = map();
+        let reversed = &map.segments[1];
+        let leds: Vec<usize> = (0..4).map(|index| reversed.physical_index(index)).collect();
+        assert_eq!(leds, vec![3, 2, 1, 0]);
+        assert_eq!(map.segments[0].physical_index(0), 2);
+        assert_eq!(map.frame_size, 10);
+    }
+
+    #[test]
+    fn physical_index_round_trips_through_frame_index() {
+        let map = map();
+        for segment in map.segments.iter() {
+            for index in 0..segment.length {
+                let led = segment.physical_index(index);
+                assert_eq!(
+                    map.frame_index(segment.channel, led),
+                    Some(segment.start + index)
+                );
+            }
+        }
+        // LEDs before the offset are not covered by any segment
+        assert_eq!(map.frame_index(0, 1), None);
+    }
+}
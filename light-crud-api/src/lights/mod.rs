@@ -1,2 +1,21 @@
+pub mod artnet;
+pub mod brightness;
 pub mod controller;
 pub mod converter;
+pub mod ddp;
+pub mod dmx;
+pub mod e131;
+pub mod live;
+pub mod mapping;
+pub mod output;
+pub mod playback;
+pub mod player;
+pub mod power;
+pub mod simulated;
+pub mod status;
+pub mod stream;
+pub mod terminal;
+pub mod timing;
+pub mod transition;
+#[cfg(feature = "rpi")]
+pub mod ws281x;
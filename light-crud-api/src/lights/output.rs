@@ -0,0 +1,28 @@
+/// One LED worth of data in the order the strip expects it, the last byte is unused for RGB strips
+pub type RawColor = [u8; 4];
+
+/// Anything the light loop can render frames to.
+///
+/// Channels are indexed the same way as the rpi_ws281x channels, so channel 0 is the entryway
+/// and channel 1 is the front of the house.
+#[allow(dead_code)]
+pub trait LedOutput {
+    /// Number of channels this output drives
+    fn channel_count(&self) -> usize;
+
+    /// Number of LEDs on the given channel
+    fn channel_len(&self, channel: usize) -> usize;
+
+    /// The buffer of the given channel
+    fn leds(&self, channel: usize) -> &[RawColor];
+
+    /// Mutable access to the buffer of the given channel, shown on the next `render`
+    fn leds_mut(&mut self, channel: usize) -> &mut [RawColor];
+
+    fn brightness(&self, channel: usize) -> u8;
+
+    fn set_brightness(&mut self, channel: usize, value: u8);
+
+    /// Push the buffers out to the LEDs
+    fn render(&mut self) -> Result<(), String>;
+}
@@ -0,0 +1,544 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::transition::{mix, Transition};
+
+use crate::config::LightsConfig;
+use crate::database::animation::Animation;
+use crate::database::frame::DataFrame;
+
+/// Slowest animation speed in frames per second
+pub const MIN_SPEED: f64 = 0.001;
+/// Fastest animation speed in frames per second, a frame every millisecond
+pub const MAX_SPEED: f64 = 1000.0;
+
+/// How the light loop fills in the time between two stored frames
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// Hold each frame until the next one is due
+    #[default]
+    None,
+    /// Blend evenly from one frame to the next
+    Linear,
+    /// Blend slowly at the start and end of each frame, quicker in the middle
+    Ease,
+}
+
+impl Interpolation {
+    /// Maps how far through a frame we are onto how much of the next frame to blend in
+    pub fn curve(&self, progress: f64) -> f64 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            Interpolation::None => 0.0,
+            Interpolation::Linear => progress,
+            Interpolation::Ease => progress * progress * (3.0 - 2.0 * progress),
+        }
+    }
+}
+
+/// What happens when an animation gets to its last frame
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum PlaybackMode {
+    /// Start again from the first frame, forever
+    #[default]
+    Loop,
+    /// Play through once and hold the last frame
+    OnceHold,
+    /// Play through once and go back to whatever was playing before
+    OnceRevert,
+    /// Play forwards then backwards, forever
+    PingPong,
+    /// Play through a set number of times and hold the last frame
+    Repeat,
+}
+
+/// An animation sent to the light loop along with how to play it
+#[derive(Clone, Debug)]
+pub struct PlayRequest {
+    pub animation: Animation,
+    pub transition: Transition,
+    pub interpolation: Interpolation,
+    pub mode: PlaybackMode,
+    /// Number of times to play through in `PlaybackMode::Repeat`
+    pub repeat: u32,
+}
+
+impl PlayRequest {
+    /// Plays the animation with the mode stored with it and the defaults from the config
+    pub fn new(animation: Animation, lights: &LightsConfig) -> Self {
+        PlayRequest {
+            mode: animation.mode.unwrap_or_default(),
+            repeat: animation.repeat_count.unwrap_or(1),
+            animation: animation,
+            transition: lights.transition,
+            interpolation: lights.interpolation,
+        }
+    }
+}
+
+/// Keeps track of which frame of an animation should be showing
+#[derive(Clone, Debug)]
+pub struct Playback {
+    pub animation: Animation,
+    pub index: usize,
+    pub interpolation: Interpolation,
+    pub mode: PlaybackMode,
+    pub repeat: u32,
+    /// Number of times the animation has played through
+    pub loops: u32,
+    /// Ping pong is on its way back to the first frame
+    reversing: bool,
+    /// Got to the end and is holding the last frame
+    finished: bool,
+    /// When the current frame was first due to be shown
+    frame_started: Instant,
+    /// When the animation was paused, None while it is playing
+    paused_at: Option<Instant>,
+}
+
+impl Playback {
+    pub fn new(request: PlayRequest, now: Instant) -> Self {
+        Playback {
+            animation: request.animation,
+            index: 0,
+            interpolation: request.interpolation,
+            mode: request.mode,
+            repeat: request.repeat,
+            loops: 0,
+            reversing: false,
+            finished: false,
+            frame_started: now,
+            paused_at: None,
+        }
+    }
+
+    /// Picks the animation back up from where it was, e.g. after a play once animation reverts
+    pub fn resume(&mut self, now: Instant) {
+        self.frame_started = now;
+        if self.paused_at.is_some() {
+            self.paused_at = Some(now);
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return self.finished;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        return self.paused_at.is_some();
+    }
+
+    /// Holds the current frame, part way through any interpolation, until `unpause` is called
+    pub fn pause(&mut self, now: Instant) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(now);
+        }
+    }
+
+    /// Carries on from where `pause` stopped, the time spent paused does not count
+    pub fn unpause(&mut self, now: Instant) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.frame_started += now.saturating_duration_since(paused_at);
+        }
+    }
+
+    /// Jumps to a stored frame, clamped to the last one, and starts showing it from the beginning
+    pub fn seek_frame(&mut self, index: usize, now: Instant) {
+        self.index = index.min(self.animation.frames.len() - 1);
+        self.reversing = false;
+        self.finished = false;
+        self.frame_started = now;
+        if self.paused_at.is_some() {
+            self.paused_at = Some(now);
+        }
+    }
+
+    /// Jumps to where the animation would be `offset` after it started, following the playback
+    /// mode, so it may have looped, turned round or finished by then
+    pub fn seek_time(&mut self, offset: Duration, now: Instant) {
+        let was_paused = self.paused_at.take().is_some();
+        self.index = 0;
+        self.loops = 0;
+        self.reversing = false;
+        self.finished = false;
+        self.frame_started = now.checked_sub(offset).unwrap_or(now);
+        self.advance(now);
+        if was_paused {
+            self.paused_at = Some(now);
+        }
+    }
+
+    /// Pauses and moves on one stored frame, wrapping round to the first
+    pub fn step_forward(&mut self, now: Instant) {
+        let index = (self.index + 1) % self.animation.frames.len();
+        self.pause(now);
+        self.seek_frame(index, now);
+    }
+
+    /// Pauses and moves back one stored frame, wrapping round to the last
+    pub fn step_back(&mut self, now: Instant) {
+        let len = self.animation.frames.len();
+        self.pause(now);
+        self.seek_frame((self.index + len - 1) % len, now);
+    }
+
+    /// True when the frame shown changes between stored frames, so the loop has to keep
+    /// rendering at the refresh rate
+    pub fn is_interpolating(&self) -> bool {
+        return self.interpolation != Interpolation::None
+            && self.paused_at.is_none()
+            && self.next_position().is_some();
+    }
+
+    /// How long each frame is held for, from the animation speed in frames per second
+    pub fn frame_period(&self) -> Duration {
+        let speed = self.animation.speed.max(MIN_SPEED).min(MAX_SPEED);
+        return Duration::from_secs_f64(1.0 / speed);
+    }
+
+    /// When the next frame is due, None once the animation has finished or while it is paused
+    pub fn next_frame_at(&self) -> Option<Instant> {
+        if self.finished || self.paused_at.is_some() {
+            return None;
+        }
+        return Some(self.frame_started + self.frame_period());
+    }
+
+    /// How many times the animation plays through before it finishes, None for forever
+    fn total_plays(&self) -> Option<u32> {
+        match self.mode {
+            PlaybackMode::Loop | PlaybackMode::PingPong => None,
+            PlaybackMode::OnceHold | PlaybackMode::OnceRevert => Some(1),
+            PlaybackMode::Repeat => Some(self.repeat.max(1)),
+        }
+    }
+
+    /// The frame index and direction after the current frame, None if this is the last frame
+    fn next_position(&self) -> Option<(usize, bool)> {
+        let last = self.animation.frames.len() - 1;
+        if self.mode == PlaybackMode::PingPong {
+            if last == 0 {
+                return Some((0, false));
+            }
+            if self.reversing {
+                return match self.index {
+                    0 => Some((1, false)),
+                    index => Some((index - 1, true)),
+                };
+            }
+            if self.index == last {
+                return Some((last - 1, true));
+            }
+            return Some((self.index + 1, false));
+        }
+        if self.index < last {
+            return Some((self.index + 1, false));
+        }
+        return match self.total_plays() {
+            Some(total) if self.loops + 1 >= total => None,
+            _ => Some((0, false)),
+        };
+    }
+
+    /// Steps in one play through, after which the position and direction are back where they
+    /// were. Ping-pong's very first frame is only shown once, so this holds from its second step.
+    fn play_length(&self) -> u64 {
+        let frames = self.animation.frames.len() as u64;
+        return match self.mode {
+            PlaybackMode::PingPong if frames > 1 => 2 * (frames - 1),
+            _ => frames,
+        };
+    }
+
+    fn step(&mut self) {
+        let next = self.next_position();
+        let wrapped = match self.mode {
+            PlaybackMode::PingPong => self.reversing && self.index == 0,
+            _ => self.index == self.animation.frames.len() - 1,
+        };
+        if wrapped || self.animation.frames.len() == 1 {
+            self.loops += 1;
+        }
+        match next {
+            Some((index, reversing)) => {
+                self.index = index;
+                self.reversing = reversing;
+            }
+            None => self.finished = true,
+        }
+    }
+
+    /// Steps forward over every frame that has finished by `now`.
+    ///
+    /// Frames are timed from when the animation started rather than from when the last one was
+    /// shown, so a late frame skips ahead instead of delaying everything after it. Returns the
+    /// number of frames stepped over.
+    ///
+    /// After a long stall, whole plays through are jumped over rather than stepped one frame
+    /// at a time.
+    pub fn advance(&mut self, now: Instant) -> u64 {
+        if self.paused_at.is_some() || self.finished {
+            return 0;
+        }
+        let period = self.frame_period();
+        let due = (now.saturating_duration_since(self.frame_started).as_nanos() / period.as_nanos())
+            as u64;
+        let play_length = self.play_length();
+        let mut stepped = 0;
+        while !self.finished && stepped < due {
+            self.step();
+            stepped += 1;
+            if stepped == play_length {
+                // a play through that would finish the animation is still stepped
+                let plays = (due - stepped) / play_length;
+                let plays = match self.total_plays() {
+                    Some(total) => plays.min(total.saturating_sub(self.loops + 1) as u64),
+                    None => plays,
+                };
+                self.loops = self.loops.saturating_add(plays.min(u32::MAX as u64) as u32);
+                stepped += plays * play_length;
+            }
+        }
+        self.frame_started += Duration::from_nanos((period.as_nanos() * stepped as u128) as u64);
+        return stepped;
+    }
+
+    pub fn current_frame(&self) -> &DataFrame {
+        return &self.animation.frames[self.index];
+    }
+
+    /// The pixels to show at `now`, blended towards the next stored frame when interpolating
+    pub fn frame_at(&self, now: Instant) -> Vec<u32> {
+        let current = &self.current_frame().data;
+        let next_index = match self.next_position() {
+            Some((index, _)) if self.interpolation != Interpolation::None => index,
+            _ => return current.clone(),
+        };
+        let next = &self.animation.frames[next_index].data;
+        let now = self.paused_at.unwrap_or(now);
+        let progress = now
+            .saturating_duration_since(self.frame_started)
+            .as_secs_f64()
+            / self.frame_period().as_secs_f64();
+        let amount = self.interpolation.curve(progress);
+        return current
+            .iter()
+            .enumerate()
+            .map(|(index, color)| mix(*color, next.get(index).copied().unwrap_or(0), amount))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An animation at 10 frames per second, so each frame is held for 100ms
+    fn playback(frames: &[u32], mode: PlaybackMode, interpolation: Interpolation) -> Playback {
+        let mut animation = Animation::new();
+        animation.speed = 10.0;
+        animation.frames = frames
+            .iter()
+            .enumerate()
+            .map(|(index, color)| DataFrame {
+                id: -1,
+                parent_id: -1,
+                frame_id: index as i64,
+                data: vec![*color],
+            })
+            .collect();
+        let mut request = PlayRequest::new(animation, &LightsConfig::default());
+        request.mode = mode;
+        request.interpolation = interpolation;
+        return Playback::new(request, Instant::now());
+    }
+
+    fn ms(milliseconds: u64) -> Duration {
+        return Duration::from_millis(milliseconds);
+    }
+
+    #[test]
+    fn ease_is_slow_at_the_ends() {
+        assert_eq!(Interpolation::Ease.curve(0.5), 0.5);
+        assert_eq!(Interpolation::Ease.curve(0.25), 0.15625);
+        assert_eq!(Interpolation::Linear.curve(2.0), 1.0);
+        assert_eq!(Interpolation::None.curve(0.9), 0.0);
+    }
+
+    #[test]
+    fn linear_interpolation_blends_towards_the_next_frame() {
+        let playback = playback(
+            &[0x000000, 0xC8C8C8],
+            PlaybackMode::Loop,
+            Interpolation::Linear,
+        );
+        let started = playback.frame_started;
+        assert_eq!(playback.frame_at(started), vec![0x000000]);
+        assert_eq!(playback.frame_at(started + ms(50)), vec![0x646464]);
+        assert!(playback.is_interpolating());
+    }
+
+    #[test]
+    fn no_interpolation_holds_each_frame() {
+        let playback = playback(
+            &[0x000000, 0xC8C8C8],
+            PlaybackMode::Loop,
+            Interpolation::None,
+        );
+        assert_eq!(
+            playback.frame_at(playback.frame_started + ms(90)),
+            vec![0x000000]
+        );
+        assert!(!playback.is_interpolating());
+    }
+
+    #[test]
+    fn paused_interpolation_holds_part_way() {
+        let mut playback = playback(
+            &[0x000000, 0xC8C8C8],
+            PlaybackMode::Loop,
+            Interpolation::Linear,
+        );
+        let started = playback.frame_started;
+        playback.pause(started + ms(50));
+        assert_eq!(playback.frame_at(started + ms(500)), vec![0x646464]);
+    }
+
+    /// Index and loop count after each frame, advancing one frame period at a time
+    fn positions(playback: &mut Playback, frames: u64) -> Vec<(usize, u32)> {
+        let started = playback.frame_started;
+        return (1..=frames)
+            .map(|frame| {
+                playback.advance(started + ms(100 * frame));
+                (playback.index, playback.loops)
+            })
+            .collect();
+    }
+
+    #[test]
+    fn ping_pong_turns_round_at_each_end() {
+        let mut playback = playback(&[1, 2, 3], PlaybackMode::PingPong, Interpolation::None);
+        assert_eq!(
+            positions(&mut playback, 8),
+            vec![
+                (1, 0),
+                (2, 0),
+                (1, 0),
+                (0, 0),
+                (1, 1),
+                (2, 1),
+                (1, 1),
+                (0, 1)
+            ]
+        );
+        assert!(!playback.is_finished());
+    }
+
+    #[test]
+    fn loop_wraps_to_the_first_frame() {
+        let mut playback = playback(&[1, 2, 3], PlaybackMode::Loop, Interpolation::None);
+        assert_eq!(
+            positions(&mut playback, 4),
+            vec![(1, 0), (2, 0), (0, 1), (1, 1)]
+        );
+    }
+
+    #[test]
+    fn repeat_plays_through_the_count_then_holds() {
+        let mut playback = playback(&[1, 2], PlaybackMode::Repeat, Interpolation::None);
+        playback.repeat = 2;
+        assert_eq!(
+            positions(&mut playback, 4),
+            vec![(1, 0), (0, 1), (1, 1), (1, 2)]
+        );
+        assert!(playback.is_finished());
+        assert_eq!(playback.next_frame_at(), None);
+    }
+
+    #[test]
+    fn once_hold_finishes_on_the_last_frame() {
+        let mut playback = playback(&[1, 2, 3], PlaybackMode::OnceHold, Interpolation::None);
+        let started = playback.frame_started;
+        // a late render skips the frames it missed rather than playing them out
+        assert_eq!(playback.advance(started + ms(1000)), 3);
+        assert!(playback.is_finished());
+        assert_eq!(playback.frame_at(started + ms(1000)), vec![3]);
+    }
+
+    #[test]
+    fn seek_time_follows_the_playback_mode() {
+        let mut playback = playback(&[1, 2, 3], PlaybackMode::PingPong, Interpolation::None);
+        let now = Instant::now();
+        playback.seek_time(ms(350), now);
+        assert_eq!((playback.index, playback.loops), (1, 0));
+    }
+
+    #[test]
+    fn a_long_stall_lands_where_stepping_frame_by_frame_would() {
+        for mode in [
+            PlaybackMode::PingPong,
+            PlaybackMode::Loop,
+            PlaybackMode::Repeat,
+        ] {
+            let mut jumped = playback(&[1, 2, 3, 4], mode, Interpolation::None);
+            jumped.repeat = 50;
+            let mut stepped = jumped.clone();
+            let started = jumped.frame_started;
+            assert_eq!(jumped.advance(started + ms(100 * 123 + 50)), 123);
+            for frame in 1..=123 {
+                stepped.advance(started + ms(100 * frame));
+            }
+            assert_eq!(
+                (
+                    jumped.index,
+                    jumped.reversing,
+                    jumped.loops,
+                    jumped.finished
+                ),
+                (
+                    stepped.index,
+                    stepped.reversing,
+                    stepped.loops,
+                    stepped.finished
+                ),
+                "{mode:?}"
+            );
+            assert_eq!(jumped.frame_started, stepped.frame_started);
+        }
+    }
+
+    #[test]
+    fn a_stall_past_the_end_finishes_on_the_last_frame() {
+        let mut playback = playback(&[1, 2, 3], PlaybackMode::Repeat, Interpolation::None);
+        playback.repeat = 4;
+        let started = playback.frame_started;
+        // the twelfth step is off the end of the fourth play through
+        assert_eq!(playback.advance(started + Duration::from_secs(3600)), 12);
+        assert!(playback.finished);
+        assert_eq!((playback.index, playback.loops), (2, 4));
+    }
+
+    #[test]
+    fn an_extreme_speed_does_not_spin() {
+        let mut playback = playback(&[1, 2, 3], PlaybackMode::Loop, Interpolation::None);
+        playback.animation.speed = 1e300;
+        assert_eq!(playback.frame_period(), ms(1));
+        let started = playback.frame_started;
+        // a day's worth of frames at the fastest speed
+        assert_eq!(
+            playback.advance(started + Duration::from_secs(86_400)),
+            86_400_000
+        );
+        assert_eq!(
+            (playback.index, playback.loops),
+            (86_400_000 % 3, 28_800_000)
+        );
+
+        playback.animation.speed = f64::NAN;
+        assert_eq!(playback.frame_period(), Duration::from_secs(1000));
+    }
+}
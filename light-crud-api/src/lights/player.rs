@@ -0,0 +1,210 @@
+use axum::{
+    extract,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+use super::brightness::{BrightnessMixer, ChannelBrightness, MasterBrightness};
+use super::live::LiveStatus;
+use super::playback::{Interpolation, Playback, PlaybackMode};
+
+use crate::config::LightsConfig;
+use crate::database::initialize::AppState;
+
+/// Transport controls for whatever the light loop is playing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayerCommand {
+    Pause,
+    /// Carries on after a pause or a stop
+    Resume,
+    StepForward,
+    StepBack,
+    /// Jump to a stored frame
+    SeekFrame(usize),
+    /// Jump to how far through the animation it would be this long after it started
+    SeekTime(Duration),
+    /// Pause at the first frame and fade the lights out
+    Stop,
+}
+
+/// What the player is showing, written by the light loop every time it renders
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PlayerStatus {
+    pub animation_id: i32,
+    pub animation_name: String,
+    pub frame_index: usize,
+    pub frame_count: usize,
+    /// Frames per second the animation was stored with
+    pub animation_fps: f64,
+    pub mode: PlaybackMode,
+    pub interpolation: Interpolation,
+    /// Number of times the animation has played through
+    pub loops: u32,
+    pub finished: bool,
+    pub paused: bool,
+    /// Showing black after a stop command
+    pub stopped: bool,
+    pub transitioning: bool,
+    pub brightness: Vec<ChannelBrightness>,
+    pub master_brightness: MasterBrightness,
+    /// Set while frames pushed in from outside are showing instead of the animation
+    pub live: Option<LiveStatus>,
+    /// When the light loop started
+    #[serde(skip)]
+    pub loop_started: Option<Instant>,
+    /// When the current animation started playing
+    #[serde(skip)]
+    pub animation_started: Option<Instant>,
+}
+
+impl PlayerStatus {
+    pub fn update(
+        &mut self,
+        playback: &Playback,
+        stopped: bool,
+        transitioning: bool,
+        lights: &LightsConfig,
+        brightness: &BrightnessMixer,
+        now: Instant,
+    ) {
+        self.animation_id = playback.animation.id;
+        self.animation_name = playback.animation.name.clone();
+        self.frame_index = playback.index;
+        self.frame_count = playback.animation.frames.len();
+        self.animation_fps = playback.animation.speed;
+        self.mode = playback.mode;
+        self.interpolation = playback.interpolation;
+        self.loops = playback.loops;
+        self.finished = playback.is_finished();
+        self.paused = playback.is_paused();
+        self.stopped = stopped;
+        self.transitioning = transitioning;
+        self.brightness = brightness.channel_status(lights, now);
+        self.master_brightness = brightness.master_status(now);
+    }
+}
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/status", get(get_status))
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route("/step/forward", post(step_forward))
+        .route("/step/back", post(step_back))
+        .route("/seek/frame/:index", post(seek_frame))
+        .route("/seek/time/:milliseconds", post(seek_time))
+        .route("/stop", post(stop))
+        .with_state(state);
+
+    index.insert("/player/status", "GET");
+    index.insert("/player/pause", "POST");
+    index.insert("/player/resume", "POST");
+    index.insert("/player/step/forward", "POST");
+    index.insert("/player/step/back", "POST");
+    index.insert("/player/seek/frame/:index", "POST");
+    index.insert("/player/seek/time/:milliseconds", "POST");
+    index.insert("/player/stop", "POST");
+    return app;
+}
+
+fn seconds_since(started: Option<Instant>) -> f64 {
+    return started.map_or(0.0, |started| started.elapsed().as_secs_f64());
+}
+
+/// Returns what is playing, how fast it is being rendered and how bright each channel is
+pub async fn get_status(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    let status = state.lights_status.lock().unwrap();
+    let player = &status.player;
+    return json!({
+        "animation": {
+            "id": player.animation_id,
+            "name": player.animation_name,
+            "frame_index": player.frame_index,
+            "frame_count": player.frame_count,
+            "mode": player.mode,
+            "interpolation": player.interpolation,
+            "loops": player.loops,
+            "finished": player.finished,
+            "paused": player.paused,
+            "stopped": player.stopped,
+            "transitioning": player.transitioning,
+            "playing_seconds": seconds_since(player.animation_started),
+        },
+        "fps": {
+            "configured": player.animation_fps,
+            "refresh_rate": state.lights.refresh_rate,
+            "target": status.timing.target_fps,
+            "measured": status.timing.measured_fps,
+        },
+        "brightness": player.brightness,
+        "master_brightness": player.master_brightness,
+        "live": player.live,
+        "playlist": status.playlist,
+        "uptime_seconds": seconds_since(player.loop_started),
+    })
+    .to_string()
+    .into_response();
+}
+
+async fn send_command(state: &AppState, command: PlayerCommand) -> Response {
+    state.send_to_player.send(command).await.unwrap();
+    return (
+        StatusCode::OK,
+        json!({"command": format!("{command:?}")}).to_string(),
+    )
+        .into_response();
+}
+
+async fn pause(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    return send_command(&state, PlayerCommand::Pause).await;
+}
+
+async fn resume(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    return send_command(&state, PlayerCommand::Resume).await;
+}
+
+async fn step_forward(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    return send_command(&state, PlayerCommand::StepForward).await;
+}
+
+async fn step_back(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    return send_command(&state, PlayerCommand::StepBack).await;
+}
+
+async fn seek_frame(
+    extract::Path(index): extract::Path<usize>,
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> Response {
+    let frame_count = state.lights_status.lock().unwrap().player.frame_count;
+    if index >= frame_count {
+        return (
+            StatusCode::BAD_REQUEST,
+            json!({"error": format!("frame {index} is out of range, the animation has {frame_count} frames")})
+                .to_string(),
+        )
+            .into_response();
+    }
+    return send_command(&state, PlayerCommand::SeekFrame(index)).await;
+}
+
+async fn seek_time(
+    extract::Path(milliseconds): extract::Path<u64>,
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> Response {
+    let offset = Duration::from_millis(milliseconds);
+    return send_command(&state, PlayerCommand::SeekTime(offset)).await;
+}
+
+async fn stop(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    return send_command(&state, PlayerCommand::Stop).await;
+}
@@ -0,0 +1,179 @@
+use serde::Serialize;
+
+use super::output::LedOutput;
+
+use crate::config::LightsConfig;
+
+#[derive(Clone, Debug)]
+struct Supply {
+    name: String,
+    max_milliamps: f64,
+    channels: Vec<usize>,
+}
+
+/// Estimated draw of one supply for the last frame, before any limiting
+#[derive(Clone, Debug, Serialize)]
+pub struct SupplyReport {
+    pub name: String,
+    pub max_amps: f64,
+    pub estimated_amps: f64,
+}
+
+/// What the limiter did to the last rendered frame
+#[derive(Clone, Debug, Serialize)]
+pub struct PowerReport {
+    /// Scale applied to every LED, 1.0 when the frame was within budget
+    pub factor: f64,
+    pub supplies: Vec<SupplyReport>,
+}
+
+impl Default for PowerReport {
+    fn default() -> Self {
+        PowerReport {
+            factor: 1.0,
+            supplies: Vec::new(),
+        }
+    }
+}
+
+/// Scales frames down so the estimated current stays inside each supply's budget.
+///
+/// The estimate is taken from the output buffers after colour correction, with the channel
+/// brightness applied, so it tracks what the strips are actually being asked to show.
+#[derive(Clone, Debug)]
+pub struct PowerLimiter {
+    supplies: Vec<Supply>,
+    milliamps_per_color: f64,
+    idle_milliamps_per_led: f64,
+}
+
+impl PowerLimiter {
+    /// Expects the config to have been validated
+    pub fn from_config(lights: &LightsConfig) -> Self {
+        let supplies = lights
+            .power
+            .supplies
+            .iter()
+            .map(|supply| Supply {
+                name: supply.name.clone(),
+                max_milliamps: supply.max_amps * 1000.0,
+                channels: supply
+                    .channels
+                    .iter()
+                    .map(|name| lights.channel_index(name).unwrap())
+                    .collect(),
+            })
+            .collect();
+        return PowerLimiter {
+            supplies: supplies,
+            milliamps_per_color: lights.power.milliamps_per_color,
+            idle_milliamps_per_led: lights.power.idle_milliamps_per_led,
+        };
+    }
+
+    /// Returns the (active, idle) current in mA the channel will draw with its current buffer
+    fn estimate_channel(&self, output: &dyn LedOutput, channel: usize) -> (f64, f64) {
+        let brightness = output.brightness(channel) as f64 / 255.0;
+        let leds = output.leds(channel);
+        let intensity: u64 = leds
+            .iter()
+            .map(|led| led[0] as u64 + led[1] as u64 + led[2] as u64 + led[3] as u64)
+            .sum();
+        let active = intensity as f64 / 255.0 * self.milliamps_per_color * brightness;
+        let idle = leds.len() as f64 * self.idle_milliamps_per_led;
+        return (active, idle);
+    }
+
+    /// Estimates the draw of the buffers in `output` and scales every LED down uniformly if any
+    /// supply would go over its budget.
+    pub fn limit(&self, output: &mut dyn LedOutput) -> PowerReport {
+        let mut report = PowerReport::default();
+        for supply in self.supplies.iter() {
+            let mut active = 0.0;
+            let mut idle = 0.0;
+            for channel in supply.channels.iter() {
+                let (channel_active, channel_idle) = self.estimate_channel(output, *channel);
+                active += channel_active;
+                idle += channel_idle;
+            }
+            if active + idle > supply.max_milliamps && active > 0.0 {
+                let factor = ((supply.max_milliamps - idle) / active).clamp(0.0, 1.0);
+                report.factor = report.factor.min(factor);
+            }
+            report.supplies.push(SupplyReport {
+                name: supply.name.clone(),
+                max_amps: supply.max_milliamps / 1000.0,
+                estimated_amps: (active + idle) / 1000.0,
+            });
+        }
+
+        if report.factor < 1.0 {
+            for channel in 0..output.channel_count() {
+                for led in output.leds_mut(channel).iter_mut() {
+                    *led = led.map(|value| (value as f64 * report.factor) as u8);
+                }
+            }
+        }
+        return report;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::simulated::SimulatedOutput;
+
+    /// 10 LEDs at 20mA a colour and 1mA idle, so 610mA at full white
+    fn limiter(max_amps: f64) -> PowerLimiter {
+        let lights: LightsConfig = toml::from_str(&format!(
+            r#"
+            [[channels]]
+            name = "a"
+            pin = 12
+            led_count = 10
+            strip_type = "ws2811_rgb"
+            brightness = 255
+
+            [[power.supplies]]
+            name = "psu"
+            max_amps = {max_amps}
+            channels = ["a"]
+            "#
+        ))
+        .unwrap();
+        return PowerLimiter::from_config(&lights);
+    }
+
+    fn white(output: &mut SimulatedOutput) {
+        output.leds_mut(0).fill([255, 255, 255, 0]);
+    }
+
+    #[test]
+    fn full_white_over_budget_is_scaled_to_fit() {
+        let mut output = SimulatedOutput::new(&[10], 255);
+        white(&mut output);
+        let report = limiter(0.31).limit(&mut output);
+        // the 10mA idle draw can not be scaled, leaving 300mA of the 600mA the colours want
+        assert_eq!(report.factor, 0.5);
+        assert!((report.supplies[0].estimated_amps - 0.61).abs() < 1e-9);
+        assert_eq!(output.leds(0)[0], [127, 127, 127, 0]);
+    }
+
+    #[test]
+    fn frame_within_budget_is_left_alone() {
+        let mut output = SimulatedOutput::new(&[10], 255);
+        white(&mut output);
+        let report = limiter(1.0).limit(&mut output);
+        assert_eq!(report.factor, 1.0);
+        assert_eq!(output.leds(0)[9], [255, 255, 255, 0]);
+    }
+
+    #[test]
+    fn channel_brightness_counts_towards_the_estimate() {
+        let mut output = SimulatedOutput::new(&[10], 0);
+        white(&mut output);
+        let report = limiter(0.31).limit(&mut output);
+        assert_eq!(report.factor, 1.0);
+        assert!((report.supplies[0].estimated_amps - 0.01).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::{Arc, Mutex};
+
+use super::output::{LedOutput, RawColor};
+
+/// A snapshot of what a simulated strip was showing when it was rendered
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DisplayedFrame {
+    pub channels: Vec<Vec<RawColor>>,
+    pub brightness: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct DisplayState {
+    last_frame: Option<DisplayedFrame>,
+    render_count: usize,
+}
+
+/// Handle onto what a `SimulatedOutput` has displayed.
+///
+/// The output itself is moved into the light loop, so keep one of these around to look at
+/// the strip from the outside.
+#[derive(Clone, Debug, Default)]
+pub struct SimulatedDisplay {
+    state: Arc<Mutex<DisplayState>>,
+}
+
+#[cfg(test)]
+impl SimulatedDisplay {
+    pub fn last_frame(&self) -> Option<DisplayedFrame> {
+        return self.state.lock().unwrap().last_frame.clone();
+    }
+
+    pub fn render_count(&self) -> usize {
+        return self.state.lock().unwrap().render_count;
+    }
+}
+
+/// An in memory LED strip that records what was rendered instead of driving any hardware
+#[derive(Debug)]
+pub struct SimulatedOutput {
+    channels: Vec<Vec<RawColor>>,
+    brightness: Vec<u8>,
+    display: SimulatedDisplay,
+}
+
+impl SimulatedOutput {
+    pub fn new(channel_lengths: &[usize], brightness: u8) -> Self {
+        SimulatedOutput {
+            channels: channel_lengths
+                .iter()
+                .map(|len| vec![[0; 4]; *len])
+                .collect(),
+            brightness: vec![brightness; channel_lengths.len()],
+            display: SimulatedDisplay::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn display(&self) -> SimulatedDisplay {
+        return self.display.clone();
+    }
+}
+
+impl LedOutput for SimulatedOutput {
+    fn channel_count(&self) -> usize {
+        return self.channels.len();
+    }
+
+    fn channel_len(&self, channel: usize) -> usize {
+        return self.channels[channel].len();
+    }
+
+    fn leds(&self, channel: usize) -> &[RawColor] {
+        return &self.channels[channel];
+    }
+
+    fn leds_mut(&mut self, channel: usize) -> &mut [RawColor] {
+        return &mut self.channels[channel];
+    }
+
+    fn brightness(&self, channel: usize) -> u8 {
+        return self.brightness[channel];
+    }
+
+    fn set_brightness(&mut self, channel: usize, value: u8) {
+        self.brightness[channel] = value;
+    }
+
+    fn render(&mut self) -> Result<(), String> {
+        let mut state = self.display.state.lock().unwrap();
+        state.last_frame = Some(DisplayedFrame {
+            channels: self.channels.clone(),
+            brightness: self.brightness.clone(),
+        });
+        state.render_count += 1;
+        return Ok(());
+    }
+}
@@ -0,0 +1,57 @@
+use axum::{
+    extract,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+
+use super::player::PlayerStatus;
+use super::power::PowerReport;
+use super::timing::TimingReport;
+
+use crate::database::initialize::AppState;
+use crate::database::playlist::PlaylistProgress;
+
+/// What the light loop is currently doing, written by the loop and read by the web server
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LightsStatus {
+    pub player: PlayerStatus,
+    pub power: PowerReport,
+    pub timing: TimingReport,
+    pub playlist: Option<PlaylistProgress>,
+}
+
+pub type SharedStatus = Arc<Mutex<LightsStatus>>;
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/power", get(get_power))
+        .route("/fps", get(get_fps))
+        .with_state(state);
+
+    index.insert("/lights/power", "GET");
+    index.insert("/lights/fps", "GET");
+    return app;
+}
+
+/// Returns the power limiting applied to the last rendered frame
+pub async fn get_power(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    let status = state.lights_status.lock().unwrap();
+    return serde_json::to_string(&status.power)
+        .unwrap()
+        .into_response();
+}
+
+/// Returns the target and measured frame rate of the light loop
+pub async fn get_fps(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    let status = state.lights_status.lock().unwrap();
+    return serde_json::to_string(&status.timing)
+        .unwrap()
+        .into_response();
+}
@@ -0,0 +1,169 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::live::bad_request;
+use super::output::LedOutput;
+
+use crate::database::initialize::AppState;
+
+/// Frames each client can fall behind by before it starts missing them
+const CLIENT_BACKLOG: usize = 4;
+/// Lowest `max_fps` a client can ask for, one frame every ten seconds
+const MIN_STREAM_FPS: f64 = 0.1;
+
+/// A rendered frame: every channel one after the other, three bytes of red, green and blue per
+/// LED with the channel brightness applied, so it is what the strips are actually showing
+pub type EncodedFrame = Arc<Vec<u8>>;
+
+/// Hands every frame the light loop renders to whoever is watching.
+///
+/// Sending never waits, a client that is too slow to keep up misses frames instead.
+#[derive(Clone, Debug)]
+pub struct FrameStream {
+    sender: broadcast::Sender<EncodedFrame>,
+    /// So a client that connects while the lights are holding a frame sees it straight away
+    latest: Arc<Mutex<Option<EncodedFrame>>>,
+}
+
+impl FrameStream {
+    pub fn new() -> Self {
+        FrameStream {
+            sender: broadcast::channel(CLIENT_BACKLOG).0,
+            latest: Arc::default(),
+        }
+    }
+
+    /// Sends out what `output` was just rendered with
+    pub fn publish(&self, output: &dyn LedOutput) {
+        let frame = Arc::new(encode(output));
+        *self.latest.lock().unwrap() = Some(frame.clone());
+        // only fails when nobody is watching
+        let _ = self.sender.send(frame);
+    }
+
+    pub fn subscribe(&self) -> (Option<EncodedFrame>, broadcast::Receiver<EncodedFrame>) {
+        let receiver = self.sender.subscribe();
+        return (self.latest.lock().unwrap().clone(), receiver);
+    }
+}
+
+fn encode(output: &dyn LedOutput) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for channel in 0..output.channel_count() {
+        let brightness = output.brightness(channel) as u32;
+        let scale = |value: u8| ((value as u32 * brightness) / 255) as u8;
+        for led in output.leds(channel) {
+            bytes.extend_from_slice(&[scale(led[0]), scale(led[1]), scale(led[2])]);
+        }
+    }
+    return bytes;
+}
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/frames", get(stream_frames))
+        .with_state(state);
+
+    index.insert("/stream/frames", "GET (WebSocket)");
+    return app;
+}
+
+/// Optional query parameters for the frame stream, e.g. `?max_fps=10`
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StreamOptions {
+    /// Most frames a second to send, frames that come in quicker are skipped. At least 0.1,
+    /// anything above `lights.refresh_rate` is treated as the refresh rate.
+    pub max_fps: Option<f64>,
+}
+
+/// Streams the frames as they are rendered over a WebSocket.
+///
+/// The first message is text describing the layout,
+/// `{"format":"rgb","channels":[{"name":"entryway","led_count":250},...]}`, then each frame is
+/// a binary message laid out as described by `EncodedFrame`.
+pub async fn stream_frames(
+    ws: WebSocketUpgrade,
+    Query(options): Query<StreamOptions>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let refresh_rate = state.lights.refresh_rate;
+    let min_interval = match options.max_fps {
+        None => None,
+        Some(max_fps) if max_fps.is_nan() || max_fps < MIN_STREAM_FPS => {
+            return bad_request(format!("max_fps must be at least {MIN_STREAM_FPS}"));
+        }
+        Some(max_fps) => Some(Duration::from_secs_f64(1.0 / max_fps.min(refresh_rate))),
+    };
+    let layout = json!({
+        "format": "rgb",
+        "channels": state
+            .lights
+            .channels
+            .iter()
+            .map(|channel| json!({"name": channel.name, "led_count": channel.led_count}))
+            .collect::<Vec<_>>(),
+    })
+    .to_string();
+    let frames = state.frame_stream.clone();
+    return ws.on_upgrade(move |socket| send_frames(socket, frames, layout, min_interval));
+}
+
+async fn send_frames(
+    mut socket: WebSocket,
+    frames: FrameStream,
+    layout: String,
+    min_interval: Option<Duration>,
+) {
+    let (latest, mut receiver) = frames.subscribe();
+    if socket.send(Message::Text(layout)).await.is_err() {
+        return;
+    }
+    let mut last_sent: Option<Instant> = None;
+    // newest frame not sent yet, frames that come in before it is sent replace it
+    let mut pending = latest;
+    loop {
+        let send_at = match (min_interval, last_sent) {
+            (Some(min_interval), Some(last_sent)) => last_sent + min_interval,
+            _ => Instant::now(),
+        };
+        if Instant::now() >= send_at {
+            if let Some(frame) = pending.take() {
+                if socket.send(Message::Binary(frame.to_vec())).await.is_err() {
+                    return;
+                }
+                last_sent = Some(Instant::now());
+                continue;
+            }
+        }
+        tokio::select! {
+            frame = receiver.recv() => match frame {
+                Ok(frame) => pending = Some(frame),
+                // fell behind, carry on from the oldest frame still buffered
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            _ = tokio::time::sleep_until(tokio::time::Instant::from_std(send_at)), if pending.is_some() => {}
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                // anything the client sends is ignored
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
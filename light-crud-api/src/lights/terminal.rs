@@ -0,0 +1,120 @@
+use std::io::{self, Write};
+
+use colored::Colorize;
+
+use super::output::{LedOutput, RawColor};
+
+/// Previews the strips in the terminal, one row of truecolor blocks per channel.
+///
+/// Each render redraws the rows in place, so the animation plays back at the same rate the
+/// light loop would drive the real LEDs. The preview is drawn on stderr, in the alternate
+/// screen, and pinned to the top of it. Everything else printed to the terminal scrolls in the
+/// region underneath, so the log lines from the other tasks do not break up the rows.
+#[derive(Debug)]
+pub struct TerminalOutput {
+    channels: Vec<Vec<RawColor>>,
+    brightness: Vec<u8>,
+    width: usize,
+    has_drawn: bool,
+}
+
+impl TerminalOutput {
+    pub fn new(channel_lengths: &[usize], brightness: u8, width: usize) -> Self {
+        TerminalOutput {
+            channels: channel_lengths
+                .iter()
+                .map(|len| vec![[0; 4]; *len])
+                .collect(),
+            brightness: vec![brightness; channel_lengths.len()],
+            width: width.max(1),
+            has_drawn: false,
+        }
+    }
+
+    /// Builds one line of blocks for a channel, sampling the LEDs down to the terminal width
+    fn draw_channel(&self, channel: usize) -> String {
+        let leds = &self.channels[channel];
+        let brightness = self.brightness[channel] as u32;
+        let columns = leds.len().min(self.width);
+        let mut line = String::new();
+        for column in 0..columns {
+            let led = leds[column * leds.len() / columns];
+            let scale = |value: u8| ((value as u32 * brightness) / 255) as u8;
+            line.push_str(
+                &"█"
+                    .truecolor(scale(led[0]), scale(led[1]), scale(led[2]))
+                    .to_string(),
+            );
+        }
+        return line;
+    }
+}
+
+impl LedOutput for TerminalOutput {
+    fn channel_count(&self) -> usize {
+        return self.channels.len();
+    }
+
+    fn channel_len(&self, channel: usize) -> usize {
+        return self.channels[channel].len();
+    }
+
+    fn leds(&self, channel: usize) -> &[RawColor] {
+        return &self.channels[channel];
+    }
+
+    fn leds_mut(&mut self, channel: usize) -> &mut [RawColor] {
+        return &mut self.channels[channel];
+    }
+
+    fn brightness(&self, channel: usize) -> u8 {
+        return self.brightness[channel];
+    }
+
+    fn set_brightness(&mut self, channel: usize, value: u8) {
+        self.brightness[channel] = value;
+    }
+
+    fn render(&mut self) -> Result<(), String> {
+        let mut screen = String::new();
+        let rows = self.channels.len();
+        if !self.has_drawn {
+            // switch to the alternate screen, clear it and start the region everything else
+            // scrolls in a blank line below the preview
+            screen.push_str(&format!(
+                "\x1b[?1049h\x1b[2J\x1b[{};r\x1b[{};1H",
+                rows + 2,
+                rows + 2
+            ));
+        }
+        // save the cursor, draw from the top left and put the cursor back where the log is
+        screen.push_str("\x1b7\x1b[H");
+        for channel in 0..rows {
+            screen.push_str(&format!(
+                "\r{:>2} {}\x1b[K\n",
+                channel,
+                self.draw_channel(channel)
+            ));
+        }
+        screen.push_str("\x1b8");
+        self.has_drawn = true;
+
+        let mut stderr = io::stderr().lock();
+        return stderr
+            .write_all(screen.as_bytes())
+            .and_then(|_| stderr.flush())
+            .map_err(|error| error.to_string());
+    }
+}
+
+impl Drop for TerminalOutput {
+    /// Gives the terminal back the way it was
+    fn drop(&mut self) {
+        if self.has_drawn {
+            let mut stderr = io::stderr().lock();
+            let _ = stderr
+                .write_all(b"\x1b[r\x1b[?1049l")
+                .and_then(|_| stderr.flush());
+        }
+    }
+}
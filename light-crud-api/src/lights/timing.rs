@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// How many of the most recent frames the measured numbers are averaged over
+const WINDOW_SIZE: usize = 120;
+
+/// Measured timing of the light loop over the last `WINDOW_SIZE` frames
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TimingReport {
+    /// Frames per second the loop is trying to hit
+    pub target_fps: f64,
+    /// Frames per second actually rendered
+    pub measured_fps: f64,
+    pub average_render_ms: f64,
+    pub max_render_ms: f64,
+    pub frames_rendered: u64,
+    /// Frames dropped because the loop fell behind
+    pub frames_skipped: u64,
+}
+
+/// Rolling record of when frames were rendered and how long they took
+#[derive(Debug)]
+pub struct FrameTimer {
+    started: VecDeque<Instant>,
+    render_times: VecDeque<Duration>,
+    frames_rendered: u64,
+    frames_skipped: u64,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        FrameTimer {
+            started: VecDeque::with_capacity(WINDOW_SIZE),
+            render_times: VecDeque::with_capacity(WINDOW_SIZE),
+            frames_rendered: 0,
+            frames_skipped: 0,
+        }
+    }
+
+    /// Records a frame that started rendering at `started` and finished at `finished`
+    pub fn record(&mut self, started: Instant, finished: Instant) {
+        if self.started.len() == WINDOW_SIZE {
+            self.started.pop_front();
+            self.render_times.pop_front();
+        }
+        self.started.push_back(started);
+        self.render_times
+            .push_back(finished.duration_since(started));
+        self.frames_rendered += 1;
+    }
+
+    /// Forgets the frames in the window, e.g. when the target rate changes. The totals are kept.
+    pub fn clear_window(&mut self) {
+        self.started.clear();
+        self.render_times.clear();
+    }
+
+    pub fn skipped(&mut self, frames: u64) {
+        self.frames_skipped += frames;
+    }
+
+    pub fn report(&self, target_fps: f64) -> TimingReport {
+        let measured_fps = match (self.started.front(), self.started.back()) {
+            (Some(first), Some(last)) if self.started.len() > 1 && last > first => {
+                (self.started.len() - 1) as f64 / last.duration_since(*first).as_secs_f64()
+            }
+            _ => 0.0,
+        };
+        let total_render: Duration = self.render_times.iter().sum();
+        let average_render = match self.render_times.len() {
+            0 => Duration::ZERO,
+            len => total_render / len as u32,
+        };
+        let max_render = self.render_times.iter().max().copied().unwrap_or_default();
+        return TimingReport {
+            target_fps: target_fps,
+            measured_fps: measured_fps,
+            average_render_ms: average_render.as_secs_f64() * 1000.0,
+            max_render_ms: max_render.as_secs_f64() * 1000.0,
+            frames_rendered: self.frames_rendered,
+            frames_skipped: self.frames_skipped,
+        };
+    }
+}
+
+/// Fixed rate ticks that are scheduled from absolute timestamps, so they do not drift.
+///
+/// If the loop falls behind, the ticks that were missed are skipped rather than rendered late.
+#[derive(Debug)]
+pub struct Ticker {
+    period: Duration,
+    next: Instant,
+}
+
+impl Ticker {
+    pub fn new(period: Duration, now: Instant) -> Self {
+        Ticker {
+            period: period,
+            next: now + period,
+        }
+    }
+
+    /// Starts counting ticks from `now` again, e.g. when blending starts
+    pub fn reset(&mut self, now: Instant) {
+        self.next = now + self.period;
+    }
+
+    pub fn next_tick(&self) -> Instant {
+        return self.next;
+    }
+
+    /// Moves past every tick that is due by `now`, returning how many were skipped on top of
+    /// the one being rendered
+    pub fn advance(&mut self, now: Instant) -> u64 {
+        let mut passed = 0;
+        while self.next <= now {
+            self.next += self.period;
+            passed += 1;
+        }
+        return passed.max(1) - 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(milliseconds: u64) -> Duration {
+        return Duration::from_millis(milliseconds);
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn measures_the_rate_and_render_times() {
+        let mut timer = FrameTimer::new();
+        let start = Instant::now();
+        for frame in 0..11 {
+            let started = start + ms(10 * frame);
+            let render = if frame == 5 { ms(13) } else { ms(2) };
+            timer.record(started, started + render);
+        }
+        timer.skipped(3);
+        let report = timer.report(100.0);
+        assert_close(report.measured_fps, 100.0);
+        assert_close(report.average_render_ms, 3.0);
+        assert_close(report.max_render_ms, 13.0);
+        assert_eq!(report.frames_rendered, 11);
+        assert_eq!(report.frames_skipped, 3);
+    }
+
+    #[test]
+    fn only_the_latest_frames_are_measured() {
+        let mut timer = FrameTimer::new();
+        let start = Instant::now();
+        // a slow start that has dropped out of the window by the end
+        for frame in 0..100 {
+            timer.record(start + ms(50 * frame), start + ms(50 * frame));
+        }
+        let fast = start + ms(50 * 100);
+        for frame in 0..WINDOW_SIZE as u64 {
+            timer.record(fast + ms(10 * frame), fast + ms(10 * frame));
+        }
+        let report = timer.report(100.0);
+        assert_close(report.measured_fps, 100.0);
+        assert_eq!(report.frames_rendered, 100 + WINDOW_SIZE as u64);
+    }
+
+    #[test]
+    fn clearing_the_window_keeps_the_totals() {
+        let mut timer = FrameTimer::new();
+        let start = Instant::now();
+        timer.record(start, start + ms(1));
+        timer.record(start + ms(10), start + ms(11));
+        timer.clear_window();
+        let report = timer.report(30.0);
+        assert_eq!(report.measured_fps, 0.0);
+        assert_eq!(report.average_render_ms, 0.0);
+        assert_eq!(report.frames_rendered, 2);
+    }
+
+    #[test]
+    fn ticks_are_kept_on_the_original_schedule() {
+        let start = Instant::now();
+        let mut ticker = Ticker::new(ms(10), start);
+        assert_eq!(ticker.next_tick(), start + ms(10));
+        // rendered a little late, the next tick is still from the start rather than from now
+        assert_eq!(ticker.advance(start + ms(13)), 0);
+        assert_eq!(ticker.next_tick(), start + ms(20));
+        // before the tick is due nothing moves
+        assert_eq!(ticker.advance(start + ms(19)), 0);
+        assert_eq!(ticker.next_tick(), start + ms(20));
+    }
+
+    #[test]
+    fn ticks_missed_while_behind_are_skipped() {
+        let start = Instant::now();
+        let mut ticker = Ticker::new(ms(10), start);
+        // the ticks at 10, 20, 30, 40 and 50ms are all due, one is rendered
+        assert_eq!(ticker.advance(start + ms(55)), 4);
+        assert_eq!(ticker.next_tick(), start + ms(60));
+
+        ticker.reset(start + ms(57));
+        assert_eq!(ticker.next_tick(), start + ms(67));
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::converter::{rgb_to_u32, ByteRGB};
+
+/// How the light loop moves from one animation to the next
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum TransitionKind {
+    /// Jump straight to the new animation
+    Cut,
+    /// Blend the old animation into the new one
+    Crossfade,
+    /// Fade the old animation out to black, then fade the new one in
+    FadeThroughBlack,
+    /// Sweep the new animation in from the start of the frame to the end
+    Wipe,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    pub duration_ms: u64,
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Transition {
+            kind: TransitionKind::Crossfade,
+            duration_ms: 1000,
+        }
+    }
+}
+
+/// Linear blend between two colours, `amount` of 0.0 is all `from` and 1.0 is all `to`
+pub fn mix(from: u32, to: u32, amount: f64) -> u32 {
+    let from = ByteRGB::from_u32(from);
+    let to = ByteRGB::from_u32(to);
+    let lerp = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * amount).round() as u8;
+    return rgb_to_u32(
+        lerp(from.red, to.red),
+        lerp(from.green, to.green),
+        lerp(from.blue, to.blue),
+    );
+}
+
+/// Blends two frames part way through a transition, `progress` runs from 0.0 to 1.0.
+///
+/// Frames of different lengths are padded with black so the result is as long as the longest.
+pub fn blend(kind: TransitionKind, from: &[u32], to: &[u32], progress: f64) -> Vec<u32> {
+    let progress = progress.clamp(0.0, 1.0);
+    let size = from.len().max(to.len());
+    let wipe_edge = (progress * size as f64).round() as usize;
+    return (0..size)
+        .map(|index| {
+            let from = from.get(index).copied().unwrap_or(0);
+            let to = to.get(index).copied().unwrap_or(0);
+            match kind {
+                TransitionKind::Cut => to,
+                TransitionKind::Crossfade => mix(from, to, progress),
+                TransitionKind::FadeThroughBlack if progress < 0.5 => mix(from, 0, progress * 2.0),
+                TransitionKind::FadeThroughBlack => mix(0, to, progress * 2.0 - 1.0),
+                TransitionKind::Wipe if index < wipe_edge => to,
+                TransitionKind::Wipe => from,
+            }
+        })
+        .collect();
+}
+
+/// A transition that is currently playing out in the light loop
+#[derive(Clone, Debug)]
+pub struct ActiveTransition {
+    /// The last frame shown by the outgoing animation
+    from: Vec<u32>,
+    kind: TransitionKind,
+    started: Instant,
+    duration: Duration,
+}
+
+impl ActiveTransition {
+    /// Returns None when the transition would not show anything, so the loop can cut instead
+    pub fn start(from: Vec<u32>, transition: Transition, now: Instant) -> Option<Self> {
+        if transition.kind == TransitionKind::Cut || transition.duration_ms == 0 {
+            return None;
+        }
+        return Some(ActiveTransition {
+            from: from,
+            kind: transition.kind,
+            started: now,
+            duration: Duration::from_millis(transition.duration_ms),
+        });
+    }
+
+    pub fn is_finished(&self, now: Instant) -> bool {
+        return now.duration_since(self.started) >= self.duration;
+    }
+
+    /// Blends the outgoing frame into `to`, the current frame of the incoming animation
+    pub fn apply(&self, to: &[u32], now: Instant) -> Vec<u32> {
+        let progress = now.duration_since(self.started).as_secs_f64() / self.duration.as_secs_f64();
+        return blend(self.kind, &self.from, to, progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossfade_half_way_mixes_the_colours() {
+        assert_eq!(mix(0xFF0000, 0x0000FF, 0.5), 0x800080);
+        assert_eq!(
+            blend(TransitionKind::Crossfade, &[0xFF0000], &[0x0000FF], 1.0),
+            vec![0x0000FF]
+        );
+    }
+
+    #[test]
+    fn fade_through_black_is_black_half_way() {
+        let from = [0xFFFFFF, 0xFFFFFF];
+        let to = [0x00FF00, 0x00FF00];
+        assert_eq!(
+            blend(TransitionKind::FadeThroughBlack, &from, &to, 0.5),
+            vec![0, 0]
+        );
+        assert_eq!(
+            blend(TransitionKind::FadeThroughBlack, &from, &to, 0.25),
+            vec![0x808080, 0x808080]
+        );
+    }
+
+    #[test]
+    fn wipe_sweeps_from_the_start_and_pads_short_frames() {
+        let from = [1, 1, 1, 1];
+        let to = [2, 2];
+        assert_eq!(
+            blend(TransitionKind::Wipe, &from, &to, 0.5),
+            vec![2, 2, 1, 1]
+        );
+        assert_eq!(
+            blend(TransitionKind::Wipe, &from, &to, 1.0),
+            vec![2, 2, 0, 0]
+        );
+    }
+
+    #[test]
+    fn cut_and_zero_length_transitions_do_not_start() {
+        let now = Instant::now();
+        let cut = Transition {
+            kind: TransitionKind::Cut,
+            duration_ms: 500,
+        };
+        let instant = Transition {
+            kind: TransitionKind::Crossfade,
+            duration_ms: 0,
+        };
+        assert!(ActiveTransition::start(vec![0], cut, now).is_none());
+        assert!(ActiveTransition::start(vec![0], instant, now).is_none());
+    }
+}
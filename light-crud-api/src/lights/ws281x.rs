@@ -0,0 +1,99 @@
+use rs_ws281x::ChannelBuilder;
+use rs_ws281x::ControllerBuilder;
+
+use super::output::{LedOutput, RawColor};
+
+use crate::config::{LightsConfig, StripType};
+
+/// The physical strips, driven through the rpi_ws281x library
+pub struct Ws281xOutput {
+    controller: rs_ws281x::Controller,
+    channel_count: usize,
+}
+
+fn to_strip_type(strip_type: StripType) -> rs_ws281x::StripType {
+    match strip_type {
+        StripType::Sk6812Rgbw => rs_ws281x::StripType::Sk6812Rgbw,
+        StripType::Sk6812Rbgw => rs_ws281x::StripType::Sk6812Rbgw,
+        StripType::Sk6812Gbrw => rs_ws281x::StripType::Sk6812Gbrw,
+        StripType::Sk6812Grbw => rs_ws281x::StripType::Sk6812Grbw,
+        StripType::Sk6812Brgw => rs_ws281x::StripType::Sk6812Brgw,
+        StripType::Sk6812Bgrw => rs_ws281x::StripType::Sk6812Bgrw,
+        StripType::Ws2811Rgb => rs_ws281x::StripType::Ws2811Rgb,
+        StripType::Ws2811Rbg => rs_ws281x::StripType::Ws2811Rbg,
+        StripType::Ws2811Grb => rs_ws281x::StripType::Ws2811Grb,
+        StripType::Ws2811Gbr => rs_ws281x::StripType::Ws2811Gbr,
+        StripType::Ws2811Brg => rs_ws281x::StripType::Ws2811Brg,
+        StripType::Ws2811Bgr => rs_ws281x::StripType::Ws2811Bgr,
+        StripType::Ws2812 => rs_ws281x::StripType::Ws2812,
+        StripType::Sk6812 => rs_ws281x::StripType::Sk6812,
+        StripType::Sk6812W => rs_ws281x::StripType::Sk6812W,
+    }
+}
+
+impl Ws281xOutput {
+    pub fn new(lights: &LightsConfig) -> Result<Self, String> {
+        // Construct a controller with a channel per configured strip. Note that the
+        // Controller is initialized by default and is cleaned up on drop
+        let mut builder = ControllerBuilder::new();
+        builder.freq(lights.frequency).dma(lights.dma);
+        for (index, channel) in lights.channels.iter().enumerate() {
+            builder.channel(
+                index, // Channel Index
+                ChannelBuilder::new()
+                    .pin(channel.pin)
+                    .count(channel.led_count as i32) // Number of LEDs
+                    .strip_type(to_strip_type(channel.strip_type))
+                    .brightness(channel.brightness) // default: 255
+                    .build(),
+            );
+        }
+        let mut controller: rs_ws281x::Controller =
+            builder.build().map_err(|error| format!("{error:?}"))?;
+
+        let leds = controller.leds_mut(0);
+
+        // thinking the format is Red, Green, Blue
+        for led in leds.iter_mut() {
+            *led = [0, 255, 0, 0];
+        }
+        controller.render().map_err(|error| format!("{error:?}"))?;
+        return Ok(Ws281xOutput {
+            controller,
+            channel_count: lights.channels.len(),
+        });
+    }
+}
+
+impl LedOutput for Ws281xOutput {
+    fn channel_count(&self) -> usize {
+        return self.channel_count;
+    }
+
+    fn channel_len(&self, channel: usize) -> usize {
+        return self.controller.leds(channel).len();
+    }
+
+    fn leds(&self, channel: usize) -> &[RawColor] {
+        return self.controller.leds(channel);
+    }
+
+    fn leds_mut(&mut self, channel: usize) -> &mut [RawColor] {
+        return self.controller.leds_mut(channel);
+    }
+
+    fn brightness(&self, channel: usize) -> u8 {
+        return self.controller.brightness(channel);
+    }
+
+    fn set_brightness(&mut self, channel: usize, value: u8) {
+        self.controller.set_brightness(channel, value);
+    }
+
+    fn render(&mut self) -> Result<(), String> {
+        return self
+            .controller
+            .render()
+            .map_err(|error| format!("{error:?}"));
+    }
+}
@@ -1,7 +1,11 @@
 mod config;
 mod database;
+mod events;
 mod lights;
+mod mqtt;
+mod solar;
 mod thread_utils;
+mod wled;
 
 use config::read_or_create_config;
 use futures::executor::block_on;
@@ -22,30 +26,80 @@ async fn wait_for_shutdown(notify: Arc<Notify>) {
 #[tokio::main]
 async fn main() {
     let path = "config.toml";
-    let config = read_or_create_config(path).unwrap();
+    let config = match read_or_create_config(path) {
+        Ok(config) => config,
+        Err(error) => {
+            println!("{}", format!("Config: {path}: {error}").red());
+            return;
+        }
+    };
     println!("{config:?}\n\n");
+    if let Err(error) = config.lights.validate() {
+        println!("{}", format!("Config: {error}").red());
+        return;
+    }
+    if let Some(Err(error)) = config.location.as_ref().map(|location| location.validate()) {
+        println!("{}", format!("Config: {error}").red());
+        return;
+    }
+    if let Some(Err(error)) = config
+        .e131
+        .as_ref()
+        .map(|e131| e131.validate(&config.lights))
+    {
+        println!("{}", format!("Config: {error}").red());
+        return;
+    }
+    if let Some(Err(error)) = config
+        .artnet
+        .as_ref()
+        .map(|artnet| artnet.validate(&config.lights))
+    {
+        println!("{}", format!("Config: {error}").red());
+        return;
+    }
 
     let notifier = NotifyChecker::new();
 
     // Spawn a task to listen for a shutdown signal (e.g., Ctrl+C)
     let shutdown_signal_notifier = notifier.clone();
-    tokio::spawn(thread_utils::wait_for_signals(shutdown_signal_notifier));
+    tokio::spawn(thread_utils::wait_for_signals(
+        shutdown_signal_notifier,
+        config.events.clone(),
+    ));
 
     let mut threads = Vec::new();
 
-    if config.debug.enable_timed_brightness {
-        let timed_brightness_notifier = notifier.clone();
-        let brightness_tx = config.brightness_comms.sending_channel.clone();
-        threads.push(tokio::spawn(thread_utils::timed_brightness(
-            brightness_tx,
-            timed_brightness_notifier,
+    // the web server, the scheduler and the MQTT bridge share the database and the channels to
+    // the controller
+    let state = match config.debug.enable_webserver
+        || config.debug.enable_scheduler
+        || config.mqtt.is_some()
+    {
+        true => Some(database::initialize::create_state(&config).await),
+        false => None,
+    };
+
+    if let (true, Some(state)) = (config.debug.enable_scheduler, &state) {
+        let scheduler_notifier = notifier.clone();
+        threads.push(tokio::spawn(database::schedule::run_scheduler(
+            state.clone(),
+            scheduler_notifier,
         )));
     }
 
-    if config.debug.enable_webserver {
+    if let (Some(mqtt), Some(state)) = (&config.mqtt, &state) {
+        threads.push(tokio::spawn(mqtt::run_bridge(
+            mqtt.clone(),
+            state.clone(),
+            notifier.clone(),
+        )));
+    }
+
+    if let (true, Some(state)) = (config.debug.enable_webserver, &state) {
         let shutdown_notify_web_server = notifier.clone();
 
-        let app = database::initialize::setup(&config).await;
+        let app = database::initialize::setup(state.clone());
 
         let listener =
             tokio::net::TcpListener::bind(format!("{}:{}", config.web.interface, config.web.port))
@@ -66,19 +120,55 @@ async fn main() {
     } else {
         println!("Controller: N/A");
     }
+    if let Some(e131) = &config.e131 {
+        threads.push(tokio::spawn(lights::e131::run_receiver(
+            e131.clone(),
+            config.lights.clone(),
+            config.live_comms.sending_channel.clone(),
+            notifier.clone(),
+        )));
+    }
+
+    if let Some(artnet) = &config.artnet {
+        threads.push(tokio::spawn(lights::artnet::run_receiver(
+            artnet.clone(),
+            config.lights.clone(),
+            config.live_comms.sending_channel.clone(),
+            notifier.clone(),
+        )));
+    }
+
+    if let Some(ddp) = &config.ddp {
+        threads.push(tokio::spawn(lights::ddp::run_receiver(
+            ddp.clone(),
+            config.lights.clone(),
+            config.ddp_stats.clone(),
+            config.live_comms.sending_channel.clone(),
+            notifier.clone(),
+        )));
+    }
+
     if config.debug.enable_lights {
         let light_shutdown_notifier = notifier.clone();
         let animation_comms_rx = config.animation_comms.receving_channel;
         let brightness_comms_rx = config.brightness_comms.receving_channel;
-        use lights::controller::light_loop;
-        
-        light_loop(
-            light_shutdown_notifier,
-            animation_comms_rx,
-            brightness_comms_rx,
-        )
-        .await;
-   
+        let player_comms_rx = config.player_comms.receving_channel;
+        let live_comms_rx = config.live_comms.receving_channel;
+        use lights::controller::{light_loop, LightLoopIo};
+
+        let io = LightLoopIo {
+            output: lights::controller::setup(&config.lights, &config.debug),
+            status: config.lights_status.clone(),
+            frame_stream: config.frame_stream.clone(),
+            events: config.events.clone(),
+            shutdown_notifier: light_shutdown_notifier,
+            animation_receiver: animation_comms_rx,
+            brightness_receiver: brightness_comms_rx,
+            player_receiver: player_comms_rx,
+            live_receiver: live_comms_rx,
+        };
+        light_loop(config.lights.clone(), io).await;
+
         // threads.push(handle);
     } else {
         println!("Not Starting Lighting Controller");
@@ -0,0 +1,438 @@
+use std::{sync::Arc, time::Duration};
+
+use rumqttc::{AsyncClient, Event as MqttEvent, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+
+use crate::config::MqttConfig;
+use crate::database::animation::Animation;
+use crate::database::frame::DataFrame;
+use crate::database::initialize::AppState;
+use crate::database::location::LedLocation;
+use crate::events::Event;
+use crate::lights::brightness::{send_brightness, BrightnessOptions, BrightnessTarget};
+use crate::lights::converter::rgb_to_u32;
+use crate::lights::playback::PlayRequest;
+use crate::lights::player::PlayerCommand;
+use crate::thread_utils::NotifyChecker;
+
+/// How long to wait before trying the broker again after the connection drops
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Where Linux reports the CPU temperature, in thousandths of a degree
+const THERMAL_ZONE: &str = "/sys/class/thermal/thermal_zone0/temp";
+
+/// Answers the `command/...` topics over MQTT.
+///
+/// Publishing to `<topic>/request` asks for data, whatever is sent is ignored and the answer
+/// comes back as JSON on `<topic>/response`.
+///
+/// * `lights/brightness/set` - every channel's level, a number from 0.0 to 1.0
+/// * `lights/color/<id>/set` - one LED, or every LED with `all`, to a colour given as
+///   `#RRGGBB`, a number or `[r, g, b]`
+/// * `lights/location/<id>/request` - where an LED is
+/// * `triggers/<name>` - passed on to `/events` as a trigger, e.g. `triggers/sensor_1`
+/// * `system/temp/request` and `system/fps/request`
+/// * `system/stop` fades the lights out, the same as `/player/stop`, and `system/start` resumes
+///   the player after a stop
+/// * `system/shutdown` shuts the server down
+///
+/// `mqtt_test.sh` checks these against a local Mosquitto broker.
+pub async fn run_bridge(mqtt: MqttConfig, state: Arc<AppState>, shutdown: NotifyChecker) {
+    println!("MQTT: Starting");
+    let mut options = MqttOptions::new(mqtt.client_id.clone(), mqtt.host.clone(), mqtt.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &mqtt.username {
+        options.set_credentials(username, mqtt.password.clone().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 32);
+    let mut bridge = Bridge {
+        root: mqtt.topic_root(),
+        colors: vec![0; state.lights.frame_size()],
+        client: client,
+        state: state,
+        shutdown: shutdown,
+    };
+
+    while !bridge.shutdown.is_notified() {
+        // only shutting down cancels a poll, a poll cut short part way through a packet would
+        // lose it
+        let event = tokio::select! {
+            event = eventloop.poll() => event,
+            _ = bridge.shutdown.wait() => break,
+        };
+        match event {
+            Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                println!("MQTT: connected to {}:{}", mqtt.host, mqtt.port);
+                // the session is not kept, so subscribe again after every reconnect
+                let topics = format!("{}/#", bridge.root);
+                if let Err(error) = bridge.client.try_subscribe(topics, QoS::AtMostOnce) {
+                    println!("MQTT: could not subscribe: {error}");
+                }
+            }
+            Ok(MqttEvent::Incoming(Packet::Publish(publish))) => {
+                bridge.on_message(&publish.topic, &publish.payload).await;
+            }
+            Ok(_) => {}
+            Err(error) => {
+                println!(
+                    "MQTT: lost {}:{} ({error}), trying again in {}s",
+                    mqtt.host,
+                    mqtt.port,
+                    RECONNECT_DELAY.as_secs()
+                );
+                tokio::select! {
+                    _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                    _ = bridge.shutdown.wait() => break,
+                }
+            }
+        }
+    }
+    let _ = bridge.client.try_disconnect();
+    println!("MQTT: Stopped");
+}
+
+struct Bridge {
+    /// `command` with the configured prefix in front
+    root: String,
+    /// What `lights/color/<id>/set` has set each LED to
+    colors: Vec<u32>,
+    client: AsyncClient,
+    state: Arc<AppState>,
+    shutdown: NotifyChecker,
+}
+
+/// What a message on one of the bridge's topics asks for
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    SetBrightness,
+    Brightness,
+    SetColor(&'a str),
+    Color(&'a str),
+    Location(&'a str),
+    Trigger(&'a str),
+    Temperature,
+    Fps,
+    Stop,
+    Start,
+    Shutdown,
+    Unknown,
+}
+
+/// A message sorted by its topic
+#[derive(Debug, PartialEq)]
+struct Route<'a> {
+    /// The topic without the root in front
+    path: &'a str,
+    command: Command<'a>,
+    /// Where the answer goes under the root, `<reply>/response`, for `.../request` topics
+    reply: Option<String>,
+}
+
+/// Works out what a message on `topic` asks for, None for topics outside `root` and for our own
+/// answers coming back round
+fn route<'a>(root: &str, topic: &'a str) -> Option<Route<'a>> {
+    let path = topic.strip_prefix(root)?.strip_prefix('/')?;
+    let parts: Vec<&str> = path.split('/').collect();
+    let reply = match parts.as_slice() {
+        [.., "response"] => return None,
+        [base @ .., "request"] => Some(base.join("/")),
+        _ => None,
+    };
+    let command = match parts.as_slice() {
+        ["lights", "brightness", "set"] => Command::SetBrightness,
+        ["lights", "brightness", "request"] => Command::Brightness,
+        ["lights", "color", id, "set"] => Command::SetColor(id),
+        ["lights", "color", id, "request"] => Command::Color(id),
+        ["lights", "location", id, "request"] => Command::Location(id),
+        ["triggers", name] => Command::Trigger(name),
+        ["system", "temp", "request"] => Command::Temperature,
+        ["system", "fps", "request"] => Command::Fps,
+        ["system", "stop"] => Command::Stop,
+        ["system", "start"] => Command::Start,
+        ["system", "shutdown"] => Command::Shutdown,
+        _ => Command::Unknown,
+    };
+    return Some(Route {
+        path: path,
+        command: command,
+        reply: reply,
+    });
+}
+
+impl Bridge {
+    async fn on_message(&mut self, topic: &str, payload: &[u8]) {
+        let Route {
+            path,
+            command,
+            reply,
+        } = match route(&self.root, topic) {
+            Some(route) => route,
+            None => return,
+        };
+        let payload = String::from_utf8_lossy(payload);
+        let payload = payload.trim();
+        println!("MQTT: {path} {payload}");
+
+        let result = match command {
+            Command::SetBrightness => self.set_brightness(payload).await,
+            Command::Brightness => self.brightness(),
+            Command::SetColor(id) => self.set_color(id, payload).await,
+            Command::Color(id) => self.color(id),
+            Command::Location(id) => self.location(id).await,
+            Command::Trigger(name) => self.trigger(name, payload),
+            Command::Temperature => temperature(),
+            Command::Fps => self.fps(),
+            Command::Stop => self.stop().await,
+            Command::Start => self.start().await,
+            Command::Shutdown => self.shutdown(),
+            Command::Unknown => Err(format!("nothing listens on {path}")),
+        };
+
+        let (reply, answer) = match (result, reply) {
+            (Ok(Some(answer)), Some(reply)) => (reply, answer),
+            (Err(error), Some(reply)) => (reply, json!({"error": error})),
+            (Err(error), None) => {
+                println!("MQTT: {path} failed: {error}");
+                return;
+            }
+            (Ok(_), None) | (Ok(None), Some(_)) => return,
+        };
+        let topic = format!("{}/{reply}/response", self.root);
+        if let Err(error) =
+            self.client
+                .try_publish(topic, QoS::AtMostOnce, false, answer.to_string())
+        {
+            println!("MQTT: could not answer {path}: {error}");
+        }
+    }
+
+    async fn set_brightness(&self, payload: &str) -> Result<Option<Value>, String> {
+        let value = match payload.parse::<f64>() {
+            Ok(value) if (0.0..=1.0).contains(&value) => value,
+            _ => {
+                return Err(format!(
+                    "expected a brightness from 0.0 to 1.0, got {payload:?}"
+                ))
+            }
+        };
+        let request = BrightnessOptions::default()
+            .into_request(BrightnessTarget::AllChannels, (value * 255.0).round() as u8);
+        send_brightness(&self.state, request).await;
+        return Ok(None);
+    }
+
+    fn brightness(&self) -> Result<Option<Value>, String> {
+        let status = self.state.lights_status.lock().unwrap();
+        return Ok(Some(json!({
+            "master": status.player.master_brightness,
+            "channels": status.player.brightness,
+        })));
+    }
+
+    /// Plays the colours set so far as a single frame, LEDs that have not been set are off
+    async fn set_color(&mut self, id: &str, payload: &str) -> Result<Option<Value>, String> {
+        let color = parse_color(payload)?;
+        match id {
+            "all" => self.colors.fill(color),
+            _ => {
+                let index = self.led_index(id)?;
+                self.colors[index] = color;
+            }
+        }
+        let mut animation = Animation::new();
+        animation.name = String::from("MQTT colours");
+        animation.frames = vec![DataFrame {
+            id: -1,
+            parent_id: -1,
+            frame_id: 0,
+            data: self.colors.clone(),
+        }];
+        let request = PlayRequest::new(animation, &self.state.lights);
+        self.state.send_to_controller.send(request).await.unwrap();
+        return Ok(None);
+    }
+
+    fn color(&self, id: &str) -> Result<Option<Value>, String> {
+        let index = self.led_index(id)?;
+        let color = self.colors[index];
+        return Ok(Some(json!({
+            "id": index,
+            "color": format!("#{color:06x}"),
+        })));
+    }
+
+    fn led_index(&self, id: &str) -> Result<usize, String> {
+        return id
+            .parse::<usize>()
+            .ok()
+            .filter(|index| *index < self.colors.len())
+            .ok_or_else(|| {
+                format!(
+                    "there is no LED {id:?}, expected all or 0 to {}",
+                    self.colors.len().saturating_sub(1)
+                )
+            });
+    }
+
+    async fn location(&self, id: &str) -> Result<Option<Value>, String> {
+        let id = id
+            .parse::<i32>()
+            .map_err(|_| format!("{id:?} is not a location id"))?;
+        let location =
+            sqlx::query_as::<_, LedLocation>("SELECT id, x, y FROM LED_Location WHERE id = ?")
+                .bind(id)
+                .fetch_one(&self.state.db)
+                .await
+                .map_err(|error| error.to_string())?;
+        return Ok(Some(serde_json::to_value(&location).unwrap()));
+    }
+
+    fn trigger(&self, name: &str, payload: &str) -> Result<Option<Value>, String> {
+        // sensors tend to send JSON, anything else is passed on as text
+        let value = match payload.is_empty() {
+            true => None,
+            false => Some(
+                serde_json::from_str::<Value>(payload)
+                    .unwrap_or_else(|_| Value::String(payload.to_string())),
+            ),
+        };
+        self.state.events.publish(Event::TriggerReceived {
+            name: name.to_string(),
+            source: "mqtt",
+            value: value,
+        });
+        return Ok(None);
+    }
+
+    fn fps(&self) -> Result<Option<Value>, String> {
+        let status = self.state.lights_status.lock().unwrap();
+        return Ok(Some(serde_json::to_value(&status.timing).unwrap()));
+    }
+
+    async fn stop(&self) -> Result<Option<Value>, String> {
+        self.state
+            .send_to_player
+            .send(PlayerCommand::Stop)
+            .await
+            .unwrap();
+        return Ok(None);
+    }
+
+    /// The same as Ctrl + C
+    fn shutdown(&self) -> Result<Option<Value>, String> {
+        println!("MQTT: asked to shut down");
+        self.state.events.publish(Event::ShutdownRequested);
+        self.shutdown.set_notified();
+        return Ok(None);
+    }
+
+    async fn start(&self) -> Result<Option<Value>, String> {
+        self.state
+            .send_to_player
+            .send(PlayerCommand::Resume)
+            .await
+            .unwrap();
+        return Ok(None);
+    }
+}
+
+fn temperature() -> Result<Option<Value>, String> {
+    let reading = std::fs::read_to_string(THERMAL_ZONE)
+        .map_err(|error| format!("could not read {THERMAL_ZONE}: {error}"))?;
+    let millidegrees = reading
+        .trim()
+        .parse::<f64>()
+        .map_err(|error| format!("could not read {THERMAL_ZONE}: {error}"))?;
+    return Ok(Some(json!({"temp": millidegrees / 1000.0})));
+}
+
+/// Reads `#RRGGBB`, `0xRRGGBB`, a plain number or `[r, g, b]`
+fn parse_color(payload: &str) -> Result<u32, String> {
+    let hex = payload
+        .strip_prefix('#')
+        .or_else(|| payload.strip_prefix("0x"));
+    let color = match (hex, serde_json::from_str::<Value>(payload)) {
+        (Some(hex), _) => u32::from_str_radix(hex, 16).ok(),
+        (None, Ok(Value::Number(number))) => number.as_u64().map(|color| color as u32),
+        (None, Ok(Value::Array(rgb))) if rgb.len() == 3 => rgb
+            .iter()
+            .map(|value| value.as_u64().filter(|value| *value <= 255))
+            .collect::<Option<Vec<u64>>>()
+            .map(|rgb| rgb_to_u32(rgb[0] as u8, rgb[1] as u8, rgb[2] as u8)),
+        _ => None,
+    };
+    return color.filter(|color| *color <= 0xFFFFFF).ok_or_else(|| {
+        format!("expected a colour like #FF8800 or [255, 136, 0], got {payload:?}")
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command<'a>(topic: &'a str) -> Option<Command<'a>> {
+        return route("walkway/command", topic).map(|route| route.command);
+    }
+
+    #[test]
+    fn routes_topics_to_commands() {
+        assert_eq!(
+            command("walkway/command/lights/brightness/set"),
+            Some(Command::SetBrightness)
+        );
+        assert_eq!(
+            command("walkway/command/lights/color/all/set"),
+            Some(Command::SetColor("all"))
+        );
+        assert_eq!(
+            command("walkway/command/lights/location/12/request"),
+            Some(Command::Location("12"))
+        );
+        assert_eq!(
+            command("walkway/command/triggers/sensor_1"),
+            Some(Command::Trigger("sensor_1"))
+        );
+        assert_eq!(command("walkway/command/system/stop"), Some(Command::Stop));
+        assert_eq!(
+            command("walkway/command/system/shutdown"),
+            Some(Command::Shutdown)
+        );
+        assert_eq!(
+            command("walkway/command/lights/flash"),
+            Some(Command::Unknown)
+        );
+    }
+
+    #[test]
+    fn requests_are_answered_on_the_response_topic() {
+        let routed = route("command", "command/system/temp/request").unwrap();
+        assert_eq!(routed.path, "system/temp/request");
+        assert_eq!(routed.reply.as_deref(), Some("system/temp"));
+        assert_eq!(route("command", "command/system/stop").unwrap().reply, None);
+    }
+
+    #[test]
+    fn ignores_other_topics_and_our_own_answers() {
+        assert_eq!(command("walkway/command/system/temp/response"), None);
+        assert_eq!(command("hallway/command/system/stop"), None);
+        assert_eq!(command("walkway/commander/system/stop"), None);
+        assert_eq!(command("walkway/command"), None);
+    }
+
+    #[test]
+    fn parses_colours() {
+        assert_eq!(parse_color("#FF8800"), Ok(0xFF8800));
+        assert_eq!(parse_color("0x00ff00"), Ok(0x00FF00));
+        assert_eq!(parse_color("255"), Ok(0x0000FF));
+        assert_eq!(parse_color("[255, 136, 0]"), Ok(0xFF8800));
+    }
+
+    #[test]
+    fn rejects_colours_out_of_range() {
+        assert!(parse_color("#1FF8800").is_err());
+        assert!(parse_color("16777216").is_err());
+        assert!(parse_color("[256, 0, 0]").is_err());
+        assert!(parse_color("[255, 0]").is_err());
+        assert!(parse_color("orange").is_err());
+        assert!(parse_color("").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::LocationConfig;
+use crate::database::initialize::AppState;
+
+/// Julian date of 2000-01-01 12:00, the epoch the sunrise equation counts from
+const J2000: f64 = 2451545.0;
+/// Julian date of 1970-01-01 00:00
+const UNIX_EPOCH: f64 = 2440587.5;
+/// Tilt of the Earth's axis in degrees
+const OBLIQUITY: f64 = 23.4397;
+
+/// Points in the day that schedules can be set relative to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum SolarEvent {
+    /// Start of civil twilight, when the sun is 6° below the horizon in the morning
+    CivilDawn,
+    Sunrise,
+    /// When the sun is highest
+    SolarNoon,
+    Sunset,
+    /// End of civil twilight, when the sun is 6° below the horizon in the evening
+    CivilDusk,
+}
+
+impl SolarEvent {
+    /// Altitude of the centre of the sun at the event in degrees, None for solar noon
+    fn altitude(&self) -> Option<f64> {
+        match self {
+            SolarEvent::SolarNoon => None,
+            // allows for refraction and the size of the sun's disc
+            SolarEvent::Sunrise | SolarEvent::Sunset => Some(-0.833),
+            SolarEvent::CivilDawn | SolarEvent::CivilDusk => Some(-6.0),
+        }
+    }
+
+    fn is_morning(&self) -> bool {
+        return matches!(self, SolarEvent::CivilDawn | SolarEvent::Sunrise);
+    }
+}
+
+fn julian_to_utc(julian: f64) -> Option<DateTime<Utc>> {
+    return DateTime::from_timestamp_millis(((julian - UNIX_EPOCH) * 86_400_000.0).round() as i64);
+}
+
+/// Works out sunrise, sunset and twilight offline with the sunrise equation, which is good
+/// to a minute or so away from the poles
+#[derive(Clone, Debug)]
+pub struct SolarCalculator {
+    latitude: f64,
+    longitude: f64,
+    timezone: Option<Tz>,
+}
+
+/// Today's times at the configured location, formatted in its timezone
+#[derive(Clone, Debug, Serialize)]
+pub struct SolarDay {
+    /// YYYY-MM-DD
+    pub date: String,
+    pub timezone: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// None when the sun does not get that low or high on this date
+    pub civil_dawn: Option<String>,
+    pub sunrise: Option<String>,
+    pub solar_noon: Option<String>,
+    pub sunset: Option<String>,
+    pub civil_dusk: Option<String>,
+    pub day_length_minutes: Option<i64>,
+}
+
+impl SolarCalculator {
+    /// Expects the config to have been validated
+    pub fn from_config(location: &LocationConfig) -> Self {
+        SolarCalculator {
+            latitude: location.latitude,
+            longitude: location.longitude,
+            timezone: location
+                .timezone
+                .as_ref()
+                .map(|timezone| timezone.parse().unwrap()),
+        }
+    }
+
+    /// The calendar date at the lights at `instant`
+    pub fn date_of<Z: chrono::TimeZone>(&self, instant: &DateTime<Z>) -> NaiveDate {
+        return match self.timezone {
+            Some(timezone) => instant.with_timezone(&timezone).date_naive(),
+            None => instant.with_timezone(&Local).date_naive(),
+        };
+    }
+
+    fn format(&self, instant: DateTime<Utc>) -> String {
+        return match self.timezone {
+            Some(timezone) => instant.with_timezone(&timezone).to_rfc3339(),
+            None => instant.with_timezone(&Local).to_rfc3339(),
+        };
+    }
+
+    /// When `event` happens on `date`, None if the sun never gets to that altitude that day
+    pub fn event_on(&self, date: NaiveDate, event: SolarEvent) -> Option<DateTime<Utc>> {
+        let days = (date - NaiveDate::from_ymd_opt(2000, 1, 1).unwrap()).num_days() as f64;
+        // 0.0008 is the difference between terrestrial time and UTC
+        let mean_solar_noon = days + 0.0008 - self.longitude / 360.0;
+        let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_noon).rem_euclid(360.0);
+        let anomaly = mean_anomaly.to_radians();
+        let center = 1.9148 * anomaly.sin()
+            + 0.0200 * (2.0 * anomaly).sin()
+            + 0.0003 * (3.0 * anomaly).sin();
+        let ecliptic_longitude = (mean_anomaly + center + 180.0 + 102.9372)
+            .rem_euclid(360.0)
+            .to_radians();
+        let transit = J2000 + mean_solar_noon + 0.0053 * anomaly.sin()
+            - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+        let altitude = match event.altitude() {
+            Some(altitude) => altitude.to_radians(),
+            None => return julian_to_utc(transit),
+        };
+        let declination = (ecliptic_longitude.sin() * OBLIQUITY.to_radians().sin()).asin();
+        let latitude = self.latitude.to_radians();
+        let cos_hour_angle = (altitude.sin() - latitude.sin() * declination.sin())
+            / (latitude.cos() * declination.cos());
+        if !(-1.0..=1.0).contains(&cos_hour_angle) {
+            return None;
+        }
+        let hour_angle = cos_hour_angle.acos().to_degrees() / 360.0;
+        return match event.is_morning() {
+            true => julian_to_utc(transit - hour_angle),
+            false => julian_to_utc(transit + hour_angle),
+        };
+    }
+
+    pub fn day(&self, date: NaiveDate) -> SolarDay {
+        let sunrise = self.event_on(date, SolarEvent::Sunrise);
+        let sunset = self.event_on(date, SolarEvent::Sunset);
+        let format = |event| self.event_on(date, event).map(|at| self.format(at));
+        return SolarDay {
+            date: date.format("%Y-%m-%d").to_string(),
+            timezone: match self.timezone {
+                Some(timezone) => timezone.name().to_string(),
+                None => String::from("system"),
+            },
+            latitude: self.latitude,
+            longitude: self.longitude,
+            civil_dawn: format(SolarEvent::CivilDawn),
+            sunrise: format(SolarEvent::Sunrise),
+            solar_noon: format(SolarEvent::SolarNoon),
+            sunset: format(SolarEvent::Sunset),
+            civil_dusk: format(SolarEvent::CivilDusk),
+            day_length_minutes: match (sunrise, sunset) {
+                (Some(sunrise), Some(sunset)) => Some((sunset - sunrise).num_minutes()),
+                _ => None,
+            },
+        };
+    }
+}
+
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/", get(get_today))
+        .route("/:date", get(get_date))
+        .with_state(state);
+
+    index.insert("/solar", "GET");
+    index.insert("/solar/:date", "GET");
+    return app;
+}
+
+fn solar_day(state: &AppState, date: Option<NaiveDate>) -> Response {
+    let calculator = match &state.solar {
+        Some(calculator) => calculator,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                json!({"error": "add a [location] with latitude and longitude to the config"})
+                    .to_string(),
+            )
+                .into_response()
+        }
+    };
+    let date = date.unwrap_or_else(|| calculator.date_of(&Utc::now()));
+    return serde_json::to_string(&calculator.day(date))
+        .unwrap()
+        .into_response();
+}
+
+/// Returns today's sunrise, sunset and civil twilight at the configured location
+pub async fn get_today(State(state): State<Arc<AppState>>) -> Response {
+    return solar_day(&state, None);
+}
+
+/// The same as `get_today` for another date, given as YYYY-MM-DD
+pub async fn get_date(Path(date): Path<String>, State(state): State<Arc<AppState>>) -> Response {
+    return match NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+        Ok(date) => solar_day(&state, Some(date)),
+        Err(error) => (
+            StatusCode::BAD_REQUEST,
+            json!({"error": format!("{date:?} is not a YYYY-MM-DD date: {error}")}).to_string(),
+        )
+            .into_response(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calculator(latitude: f64, longitude: f64) -> SolarCalculator {
+        return SolarCalculator {
+            latitude: latitude,
+            longitude: longitude,
+            timezone: None,
+        };
+    }
+
+    fn assert_near(actual: Option<DateTime<Utc>>, expected: &str) {
+        let expected: DateTime<Utc> = expected.parse().unwrap();
+        let difference = (actual.unwrap() - expected).num_seconds().abs();
+        assert!(difference <= 120, "{actual:?} is not near {expected}");
+    }
+
+    #[test]
+    fn london_on_the_summer_solstice() {
+        // published times are 04:43 and 21:21 BST, which is UTC+1
+        let london = calculator(51.5074, -0.1278);
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        assert_near(
+            london.event_on(date, SolarEvent::Sunrise),
+            "2024-06-21T03:43:00Z",
+        );
+        assert_near(
+            london.event_on(date, SolarEvent::SolarNoon),
+            "2024-06-21T12:02:00Z",
+        );
+        assert_near(
+            london.event_on(date, SolarEvent::Sunset),
+            "2024-06-21T20:21:00Z",
+        );
+    }
+
+    #[test]
+    fn no_sunset_under_the_midnight_sun() {
+        let tromso = calculator(69.6492, 18.9553);
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        assert_eq!(tromso.event_on(date, SolarEvent::Sunset), None);
+        assert!(tromso.event_on(date, SolarEvent::SolarNoon).is_some());
+    }
+}
@@ -1,13 +1,11 @@
-use chrono::{Local, Timelike};
-use std::{
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    time::Duration,
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
 };
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Notify;
+
+use crate::events::{Event, EventBus};
 // use chrono::DateTime;
 
 #[derive(Debug, Clone)]
@@ -34,15 +32,31 @@ impl NotifyChecker {
 
     pub fn set_notified(&self) {
         self.flag.store(true, Ordering::SeqCst);
+        // wakes everyone in `wait`, and leaves a permit for `wait_for_shutdown` in main
+        self.notify.notify_waiters();
         self.notify.notify_one();
     }
 
     pub fn is_notified(&self) -> bool {
         self.flag.load(Ordering::SeqCst)
     }
+
+    /// Waits until `set_notified` is called, however many tasks are waiting
+    pub async fn wait(&self) {
+        while !self.is_notified() {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            // registers the wait before checking the flag, so a notify in between is not missed
+            notified.as_mut().enable();
+            if self.is_notified() {
+                return;
+            }
+            notified.await;
+        }
+    }
 }
 
-pub async fn wait_for_signals(notify: NotifyChecker) {
+pub async fn wait_for_signals(notify: NotifyChecker, events: EventBus) {
     let mut interrupt = signal(SignalKind::interrupt()).unwrap();
     let mut terminate = signal(SignalKind::terminate()).unwrap();
 
@@ -50,22 +64,7 @@ pub async fn wait_for_signals(notify: NotifyChecker) {
         _ = interrupt.recv() => println!("Received SIGINT, shutting down..."),
         _ = terminate.recv() => println!("Received SIGTERM, shutting down..."),
     }
+    events.publish(Event::ShutdownRequested);
     notify.set_notified();
     println!("Wait for Signal: Send and Stopping")
 }
-
-pub async fn timed_brightness(sender: tokio::sync::mpsc::Sender<u8>, shutdown: NotifyChecker) {
-    let night_brightness: u8 = 100;
-    let day_brightness: u8 = 1;
-    println!("Timed Brightness: Starting");
-    while !shutdown.is_notified() {
-        let now = Local::now();
-        if now.time().hour() > 15 {
-            sender.send(night_brightness).await.unwrap();
-        } else if now.time().hour() > 6 {
-            sender.send(day_brightness).await.unwrap();
-        }
-        tokio::time::sleep(Duration::from_secs(10)).await;
-    }
-    println!("Timed Brightness: Stopped");
-}
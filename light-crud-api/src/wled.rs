@@ -0,0 +1,522 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::database::animation::{Animation, PlayOptions};
+use crate::database::frame_data::FrameMetadata;
+use crate::database::initialize::AppState;
+use crate::lights::brightness::{BrightnessOptions, BrightnessTarget};
+use crate::lights::converter::rgb_to_u32;
+use crate::lights::live::bad_request;
+use crate::lights::player::PlayerCommand;
+
+/// The WLED release whose API this follows, clients check it to see what they can send
+const WLED_VERSION: &str = "0.14.4";
+const WLED_BUILD: u32 = 2405180;
+const NAME: &str = env!("CARGO_PKG_NAME");
+/// Effect 0, every effect after it is a stored animation
+const SOLID: &str = "Solid";
+/// Name of the animation a colour fill plays as
+const FILL_NAME: &str = "WLED colour";
+/// WLED's own starting colour, until a client picks another
+pub const DEFAULT_COLOR: [u8; 3] = [255, 160, 0];
+
+/// The colour WLED clients last filled the lights with
+pub type SharedColor = Arc<Mutex<[u8; 3]>>;
+
+/// The parts of a WLED state update that map onto the player, anything else a client sends
+/// is ignored
+#[derive(Debug, Default, Deserialize)]
+struct StateUpdate {
+    on: Option<Toggle>,
+    bri: Option<u8>,
+    /// In tenths of a second. Only used for this update, the config sets the default.
+    transition: Option<u64>,
+    /// Transition for this update only, in tenths of a second
+    tt: Option<u64>,
+    /// Id of the animation to play, -1 for none
+    ps: Option<i32>,
+    seg: Option<Segments>,
+    /// Answer with the new state rather than `{"success": true}`
+    #[serde(default)]
+    v: bool,
+}
+
+impl StateUpdate {
+    /// The transition for this update in milliseconds, `tt` before `transition`
+    fn transition_ms(&self) -> Result<Option<u64>, String> {
+        return match self.tt.or(self.transition) {
+            Some(tenths) => match tenths.checked_mul(100) {
+                Some(milliseconds) => Ok(Some(milliseconds)),
+                None => Err(format!(
+                    "a transition of {tenths} tenths of a second is too long"
+                )),
+            },
+            None => Ok(None),
+        };
+    }
+}
+
+/// `true`, `false` or `"t"` to flip it
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Toggle {
+    Set(bool),
+    Flip(String),
+}
+
+/// Clients send either one segment or a list of them, only the first is used as there is
+/// just the one segment covering every LED
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Segments {
+    One(SegmentUpdate),
+    Many(Vec<SegmentUpdate>),
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SegmentUpdate {
+    /// Primary, secondary and tertiary colours, only the primary is shown
+    col: Option<Vec<WledColor>>,
+    fx: Option<usize>,
+}
+
+/// `[r, g, b]`, `[r, g, b, w]` or `"RRGGBB"`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum WledColor {
+    Rgb(Vec<u8>),
+    Hex(String),
+}
+
+impl WledColor {
+    /// None for an empty colour, which clients send to leave it as it is
+    fn into_rgb(self) -> Result<Option<[u8; 3]>, String> {
+        match self {
+            WledColor::Rgb(rgb) if rgb.is_empty() => return Ok(None),
+            WledColor::Rgb(rgb) if rgb.len() >= 3 => return Ok(Some([rgb[0], rgb[1], rgb[2]])),
+            WledColor::Hex(hex) if hex.is_empty() => return Ok(None),
+            WledColor::Hex(hex) if hex.len() == 6 || hex.len() == 8 => {
+                if let Ok(color) = u32::from_str_radix(&hex[hex.len() - 6..], 16) {
+                    let [_, r, g, b] = color.to_be_bytes();
+                    return Ok(Some([r, g, b]));
+                }
+            }
+            _ => {}
+        }
+        return Err(String::from(
+            "expected colours like [255, 160, 0] or \"FFA000\"",
+        ));
+    }
+}
+
+/// The WLED JSON API, enough of it for Home Assistant, the WLED app and other WLED controllers
+/// to drive the lights as one segment.
+///
+/// * `on` stops the player or resumes it
+/// * `bri` sets the master brightness
+/// * `ps` plays the stored animation with that id, `/presets.json` lists them
+/// * `seg` with a colour fills every LED with it, effect 0 is the fill and every other effect
+///   is a stored animation in id order
+pub fn router(index: &mut HashMap<&'static str, &str>, state: Arc<AppState>) -> Router {
+    let app = Router::new()
+        .route("/json", get(get_all).post(post_state))
+        .route("/json/", get(get_all).post(post_state))
+        .route("/json/state", get(get_state).post(post_state))
+        .route("/json/si", get(get_state_info).post(post_state))
+        .route("/json/info", get(get_info))
+        .route("/json/effects", get(get_effects))
+        .route("/json/palettes", get(get_palettes))
+        .route("/presets.json", get(get_presets))
+        .with_state(state);
+
+    index.insert("/json", "GET,POST");
+    index.insert("/json/state", "GET,POST");
+    index.insert("/json/si", "GET,POST");
+    index.insert("/json/info", "GET");
+    index.insert("/json/effects", "GET");
+    index.insert("/json/palettes", "GET");
+    index.insert("/presets.json", "GET");
+    return app;
+}
+
+/// Stored animations in id order, which is also the order of the effects after `Solid`
+async fn animations(state: &AppState) -> Result<Vec<FrameMetadata>, Response> {
+    return sqlx::query_as::<_, FrameMetadata>(
+        "SELECT id, name, speed, mode, repeat_count FROM Frame_Metadata ORDER BY id",
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": error.to_string()})),
+        )
+            .into_response()
+    });
+}
+
+fn state_value(state: &AppState, animations: &[FrameMetadata]) -> Value {
+    let color = *state.wled_color.lock().unwrap();
+    let status = state.lights_status.lock().unwrap();
+    let player = &status.player;
+    let stored = animations
+        .iter()
+        .position(|animation| animation.id == player.animation_id);
+    let led_count = state.lights.frame_size();
+    return json!({
+        "on": !player.stopped,
+        "bri": player.master_brightness.level,
+        "transition": state.lights.transition.duration_ms / 100,
+        "ps": stored.map_or(-1, |_| player.animation_id),
+        "pl": -1,
+        "nl": {"on": false, "dur": 60, "mode": 1, "tbri": 0, "rem": -1},
+        "udpn": {"send": false, "recv": false},
+        "lor": 0,
+        "mainseg": 0,
+        "seg": [{
+            "id": 0,
+            "start": 0,
+            "stop": led_count,
+            "len": led_count,
+            "grp": 1,
+            "spc": 0,
+            "of": 0,
+            "on": !player.stopped,
+            "frz": player.paused,
+            "bri": 255,
+            "cct": 127,
+            "col": [color, [0, 0, 0], [0, 0, 0]],
+            "fx": stored.map_or(0, |position| position + 1),
+            "sx": 128,
+            "ix": 128,
+            "pal": 0,
+            "sel": true,
+            "rev": false,
+            "mi": false,
+        }],
+    });
+}
+
+fn info_value(state: &AppState, animations: &[FrameMetadata]) -> Value {
+    let status = state.lights_status.lock().unwrap();
+    let player = &status.player;
+    let supplies = &status.power.supplies;
+    let milliamps = |amps: f64| (amps * 1000.0).round() as u64;
+    // the estimate is taken before the limiter scales the frame down
+    let power: u64 = supplies
+        .iter()
+        .map(|supply| milliamps(supply.estimated_amps * status.power.factor))
+        .sum();
+    let max_power: u64 = supplies
+        .iter()
+        .map(|supply| milliamps(supply.max_amps))
+        .sum();
+    return json!({
+        "ver": WLED_VERSION,
+        "vid": WLED_BUILD,
+        "leds": {
+            "count": state.lights.frame_size(),
+            "pwr": power,
+            "fps": status.timing.measured_fps.round() as u64,
+            "maxpwr": max_power,
+            "maxseg": 1,
+            "seglc": [1],
+            "lc": 1,
+            "rgbw": false,
+            "wv": false,
+            "cct": false,
+        },
+        "str": false,
+        "name": NAME,
+        // WLED's own sync protocol is not spoken
+        "udpport": 0,
+        "live": player.live.is_some(),
+        "liveseg": -1,
+        "lm": player.live.as_ref().map_or(Value::from(""), |live| json!(live.source)),
+        "lip": "",
+        "ws": -1,
+        "fxcount": animations.len() + 1,
+        "palcount": 1,
+        "wifi": {"bssid": "", "rssi": 0, "signal": 100, "channel": 0},
+        "fs": {"u": 0, "t": 0, "pmt": 0},
+        "ndc": 0,
+        "arch": std::env::consts::ARCH,
+        "core": env!("CARGO_PKG_VERSION"),
+        "freeheap": 0,
+        "uptime": player.loop_started.map_or(0, |started| started.elapsed().as_secs()),
+        "opt": 0,
+        "brand": "WLED",
+        "product": NAME,
+        "mac": mac_address(),
+        "ip": "",
+    });
+}
+
+fn effect_names(animations: &[FrameMetadata]) -> Vec<&str> {
+    let mut names = vec![SOLID];
+    names.extend(animations.iter().map(|animation| animation.name.as_str()));
+    return names;
+}
+
+/// The first network interface's hardware address without the colons, which clients use to
+/// tell devices apart
+fn mac_address() -> String {
+    let mut interfaces: Vec<_> = match std::fs::read_dir("/sys/class/net") {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    interfaces.sort();
+    return interfaces
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path.join("address")).ok())
+        .map(|address| address.trim().replace(':', ""))
+        .find(|address| address.len() == 12 && address != "000000000000")
+        .unwrap_or_else(|| String::from("000000000000"));
+}
+
+/// Returns the state, info, effects and palettes together, as WLED does
+pub async fn get_all(State(state): State<Arc<AppState>>) -> Response {
+    let animations = match animations(&state).await {
+        Ok(animations) => animations,
+        Err(error) => return error,
+    };
+    return Json(json!({
+        "state": state_value(&state, &animations),
+        "info": info_value(&state, &animations),
+        "effects": effect_names(&animations),
+        "palettes": ["Default"],
+    }))
+    .into_response();
+}
+
+pub async fn get_state(State(state): State<Arc<AppState>>) -> Response {
+    let animations = match animations(&state).await {
+        Ok(animations) => animations,
+        Err(error) => return error,
+    };
+    return Json(state_value(&state, &animations)).into_response();
+}
+
+pub async fn get_state_info(State(state): State<Arc<AppState>>) -> Response {
+    let animations = match animations(&state).await {
+        Ok(animations) => animations,
+        Err(error) => return error,
+    };
+    return Json(json!({
+        "state": state_value(&state, &animations),
+        "info": info_value(&state, &animations),
+    }))
+    .into_response();
+}
+
+pub async fn get_info(State(state): State<Arc<AppState>>) -> Response {
+    let animations = match animations(&state).await {
+        Ok(animations) => animations,
+        Err(error) => return error,
+    };
+    return Json(info_value(&state, &animations)).into_response();
+}
+
+pub async fn get_effects(State(state): State<Arc<AppState>>) -> Response {
+    let animations = match animations(&state).await {
+        Ok(animations) => animations,
+        Err(error) => return error,
+    };
+    return Json(json!(effect_names(&animations))).into_response();
+}
+
+/// Colours always come from the animation, so there is only the one palette
+pub async fn get_palettes() -> Response {
+    return Json(json!(["Default"])).into_response();
+}
+
+/// Lists the stored animations as presets, keyed by id. WLED keeps preset 0 empty.
+pub async fn get_presets(State(state): State<Arc<AppState>>) -> Response {
+    let animations = match animations(&state).await {
+        Ok(animations) => animations,
+        Err(error) => return error,
+    };
+    let mut presets = Map::new();
+    presets.insert(String::from("0"), json!({}));
+    for animation in animations.iter() {
+        presets.insert(animation.id.to_string(), json!({"n": animation.name}));
+    }
+    return Json(Value::Object(presets)).into_response();
+}
+
+/// Applies a WLED state update. Everything is checked before anything is sent to the light
+/// loop, so a bad update changes nothing.
+pub async fn post_state(State(state): State<Arc<AppState>>, payload: String) -> Response {
+    let update = match serde_json::from_str::<StateUpdate>(&payload) {
+        Ok(update) => update,
+        Err(error) => return bad_request(format!("not a WLED state: {error}")),
+    };
+    let transition_ms = match update.transition_ms() {
+        Ok(transition_ms) => transition_ms,
+        Err(error) => return bad_request(error),
+    };
+
+    let segment = match update.seg {
+        Some(Segments::One(segment)) => segment,
+        Some(Segments::Many(segments)) => segments.into_iter().next().unwrap_or_default(),
+        None => SegmentUpdate::default(),
+    };
+    let color = match segment.col.and_then(|colors| colors.into_iter().next()) {
+        Some(color) => match color.into_rgb() {
+            Ok(color) => color,
+            Err(error) => return bad_request(error),
+        },
+        None => None,
+    };
+
+    let command = match update.on {
+        Some(Toggle::Set(true)) => Some(PlayerCommand::Resume),
+        Some(Toggle::Set(false)) => Some(PlayerCommand::Stop),
+        Some(Toggle::Flip(flip)) if flip == "t" => {
+            match state.lights_status.lock().unwrap().player.stopped {
+                true => Some(PlayerCommand::Resume),
+                false => Some(PlayerCommand::Stop),
+            }
+        }
+        Some(Toggle::Flip(other)) => {
+            return bad_request(format!(
+                "expected on to be true, false or \"t\", got {other:?}"
+            ))
+        }
+        None => None,
+    };
+
+    // a preset wins over an effect, which wins over a plain colour change
+    let animation_id = match (update.ps.filter(|preset| *preset > 0), segment.fx) {
+        (Some(preset), _) => Some(preset),
+        (None, Some(effect)) if effect > 0 => {
+            let animations = match animations(&state).await {
+                Ok(animations) => animations,
+                Err(error) => return error,
+            };
+            match animations.get(effect - 1) {
+                Some(animation) => Some(animation.id),
+                None => {
+                    return bad_request(format!(
+                        "there is no effect {effect}, expected 0 to {}",
+                        animations.len()
+                    ))
+                }
+            }
+        }
+        _ => None,
+    };
+    let animation = match animation_id {
+        Some(id) => match Animation::get_from_db(id, &state.db) {
+            Ok(animation) => Some(animation),
+            Err(sqlx::Error::RowNotFound) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({"error": format!("there is no preset {id}")})),
+                )
+                    .into_response()
+            }
+            Err(error) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({"error": error.to_string()})),
+                )
+                    .into_response()
+            }
+        },
+        None => None,
+    };
+
+    if let Some(color) = color {
+        *state.wled_color.lock().unwrap() = color;
+    }
+    let animation = match animation {
+        Some(animation) => Some(animation),
+        None if color.is_some() || segment.fx == Some(0) => {
+            let [r, g, b] = *state.wled_color.lock().unwrap();
+            let mut fill =
+                Animation::new_with_single_frame(rgb_to_u32(r, g, b), state.lights.frame_size());
+            fill.name = String::from(FILL_NAME);
+            Some(fill)
+        }
+        None => None,
+    };
+    if let Some(animation) = animation {
+        let options = PlayOptions {
+            duration_ms: transition_ms,
+            ..PlayOptions::default()
+        };
+        let request = options.into_request(animation, &state.lights);
+        state.send_to_controller.send(request).await.unwrap();
+    }
+    if let Some(bri) = update.bri {
+        let options = BrightnessOptions {
+            duration_ms: transition_ms,
+            curve: None,
+        };
+        let request = options.into_request(BrightnessTarget::Master, bri);
+        state.send_to_brightness.send(request).await.unwrap();
+    }
+    if let Some(command) = command {
+        state.send_to_player.send(command).await.unwrap();
+    }
+
+    if !update.v {
+        return Json(json!({"success": true})).into_response();
+    }
+    let animations = match animations(&state).await {
+        Ok(animations) => animations,
+        Err(error) => return error,
+    };
+    return Json(state_value(&state, &animations)).into_response();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::header;
+
+    fn update(json: &str) -> StateUpdate {
+        return serde_json::from_str(json).unwrap();
+    }
+
+    #[test]
+    fn transitions_are_in_tenths_of_a_second() {
+        assert_eq!(
+            update(r#"{"transition": 7}"#).transition_ms(),
+            Ok(Some(700))
+        );
+        assert_eq!(
+            update(r#"{"transition": 7, "tt": 2}"#).transition_ms(),
+            Ok(Some(200))
+        );
+        assert_eq!(update(r#"{"on": true}"#).transition_ms(), Ok(None));
+    }
+
+    #[test]
+    fn rejects_a_transition_too_long_to_count_in_milliseconds() {
+        let update = update(&format!(r#"{{"tt": {}}}"#, u64::MAX / 10));
+        assert!(update.transition_ms().is_err());
+    }
+
+    #[tokio::test]
+    async fn responses_are_json() {
+        let response = get_palettes().await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let response = bad_request("no".to_string());
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    }
+}
@@ -72,6 +72,21 @@ def create_animation() -> None:
         print(f"{frame_response.json()=}")
         working_arr = np.roll(working_arr, 1, axis=0).astype(int).tolist()
 
+def get_player_status() -> dict:
+    status_response = requests.get(f"{base_url}/player/status")
+    if status_response.status_code != 200:
+        print("ERROR:", status_response.text)
+    return status_response.json()
+
+def watch_player_status(interval:float=1.0) -> None:
+    while True:
+        status = get_player_status()
+        animation = status["animation"]
+        print(f"{animation['id']} {animation['name']!r} frame {animation['frame_index']+1}/{animation['frame_count']} "
+              f"{status['fps']['measured']:.1f}/{status['fps']['configured']:.1f} fps "
+              f"brightness {[channel['brightness'] for channel in status['brightness']]}")
+        time.sleep(interval)
+
 def from_u32(value:int) -> list:
     return [(value >> 16) & 0xFF, (value>>8) & 0xFF, value & 0xFF]
 
@@ -82,6 +97,7 @@ def main() -> None:
     # call_crud_endpoints(base_url=base_url)
     # create_n_locations(250)
     # delete_range_of_locations(1,279)
+    # watch_player_status()
     create_animation()
 
 if __name__ == "__main__":